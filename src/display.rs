@@ -0,0 +1,69 @@
+use std::borrow::Cow;
+use std::path::Path;
+
+// render a path for display, replacing invalid UTF-8 with U+FFFD
+// or, when escaping, with `\xNN` byte escapes (control characters and
+// backslashes are escaped too so that the output can be reversed)
+pub fn display_path(path: &Path, escape: bool) -> Cow<'_, str> {
+    if !escape {
+        return path.to_string_lossy();
+    }
+    Cow::Owned(escape_bytes(&path_bytes(path)))
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(path.as_os_str().as_bytes())
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    match path.to_string_lossy() {
+        Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+        Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+    }
+}
+
+fn escape_bytes(mut bytes: &[u8]) -> String {
+    let mut escaped = String::with_capacity(bytes.len());
+    while !bytes.is_empty() {
+        let (valid, invalid) = match std::str::from_utf8(bytes) {
+            Ok(valid) => (valid, &[][..]),
+            Err(error) => {
+                let (valid, rest) = bytes.split_at(error.valid_up_to());
+                let invalid_len = error.error_len().unwrap_or(rest.len());
+                // valid_up_to guarantees the prefix is valid UTF-8
+                (std::str::from_utf8(valid).unwrap(), &rest[..invalid_len])
+            }
+        };
+        for c in valid.chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '\n' => escaped.push_str("\\n"),
+                '\t' => escaped.push_str("\\t"),
+                '\r' => escaped.push_str("\\r"),
+                c if c.is_control() => escaped.extend(c.escape_unicode()),
+                c => escaped.push(c),
+            }
+        }
+        for byte in invalid {
+            escaped.push_str(&format!("\\x{:02X}", byte));
+        }
+        bytes = &bytes[valid.len() + invalid.len()..];
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_bytes() {
+        assert_eq!(escape_bytes(b"plain/path"), "plain/path");
+        assert_eq!(escape_bytes(b"caf\xe9"), "caf\\xE9");
+        assert_eq!(escape_bytes(b"a\nb\\c"), "a\\nb\\\\c");
+        assert_eq!(escape_bytes(b"r\xc3\xa9sum\xc3\xa9\xff"), "r\u{e9}sum\u{e9}\\xFF");
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use dir_stat::file_size::sizes;
+use dir_stat::scan_tree::NodeIndex;
+use dir_stat::types::{NodeType, ScanResult, SizeMode, SpecialEntry};
+
+use crate::utils::display_path;
+
+// the switches of `du` that change what is printed
+pub struct DuOptions {
+    // -a, files as well as directories
+    pub all: bool,
+    // -s, only the total of the root
+    pub summarize: bool,
+    // -h, powers of 1024 with a unit instead of 1K blocks
+    pub human_readable: bool,
+    // -d, nothing deeper than this below the root
+    pub max_depth: Option<usize>,
+}
+
+// an entry on the way through the tree
+enum Entry {
+    // a node of the tree, directories come up twice: first to queue their
+    // contents and once these are printed to be printed themselves
+    Node(NodeIndex, bool),
+    // a counted symlink or special file, printed like a file
+    Special(u64),
+}
+
+// fts, and with it GNU du, visits the entries of larger directories by inode
+const INODE_SORT_THRESHOLD: usize = 10000;
+
+// file systems on which fts does not sort by inode as it would not speed up
+// anything there, see gnulib's dirent_inode_sort_may_be_useful
+const UNSORTED_FS_TYPES: [&str; 5] = ["tmpfs", "nfs", "nfs4", "cifs", "smb3"];
+
+// print the scan like `du`, see `write_du`
+pub fn print_du(scan: &ScanResult, root: &Path, options: &DuOptions, escape: bool) -> Result<(), String> {
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    match write_du(&mut out, scan, root, options, escape).and_then(|()| out.flush()) {
+        Err(error) if error.kind() != io::ErrorKind::BrokenPipe => Err(error.to_string()),
+        _ => Ok(()),
+    }
+}
+
+// write the scan like `du`, every directory after its contents in the order
+// du visits them, paths start with `root` as given on the command line
+fn write_du<W: Write>(mut out: W, scan: &ScanResult, root: &Path, options: &DuOptions, escape: bool) -> io::Result<()> {
+    let tree = &scan.tree;
+    let max_depth = if options.summarize { Some(0) } else { options.max_depth };
+
+    // du does not list a directory again that it has already been through,
+    // other paths of a hardlinked file are not part of the tree
+    let aliases: HashSet<&Path> = scan.directory_aliases.iter().map(|alias| alias.path.as_path()).collect();
+    let mut specials: HashMap<NodeIndex, Vec<&SpecialEntry>> = HashMap::new();
+    for (directory, entry) in scan.counted_special.iter() {
+        specials.entry(*directory).or_default().push(entry);
+    }
+    let fs_types: HashMap<u64, &str> = scan
+        .devices
+        .iter()
+        .filter_map(|usage| Some((usage.device, usage.fs_type.as_deref()?)))
+        .collect();
+
+    let mut stack: Vec<(Entry, PathBuf, usize)> = vec![(Entry::Node(tree.root(), false), root.to_path_buf(), 0)];
+    while let Some((entry, path, depth)) = stack.pop() {
+        let (size, is_directory) = match entry {
+            Entry::Node(index, false) if tree.get(index).node_type == NodeType::Directory => {
+                stack.push((Entry::Node(index, true), path.clone(), depth));
+                // (position, inode, (entry, name)) of everything shown of the
+                // directory, the number of entries it has is told by the
+                // last position as du counts the ones not shown as well
+                let mut contents: Vec<(usize, u64, (Entry, &OsStr))> = Vec::new();
+                let mut entries = 0;
+                for (child, node) in tree.children(index) {
+                    let (position, inode) = tree.details(child).map_or((0, 0), |details| (details.position, details.id.1));
+                    entries = entries.max(position + 1);
+                    let skipped = if node.node_type == NodeType::Directory {
+                        aliases.contains(tree.path(child).as_path())
+                    } else {
+                        !options.all
+                    };
+                    if !skipped {
+                        contents.push((position, inode, (Entry::Node(child, false), &node.name)));
+                    }
+                }
+                for special in specials.get(&index).into_iter().flatten() {
+                    entries = entries.max(special.position + 1);
+                    if let Some(name) = special.path.file_name().filter(|_| options.all) {
+                        contents.push((special.position, special.id.1, (Entry::Special(special.size), name)));
+                    }
+                }
+                let device = tree.details(index).map(|details| details.id.0);
+                let fs_type = device.and_then(|device| fs_types.get(&device).copied());
+                visit_order(&mut contents, entries, fs_type);
+                // pushed in reverse so that the first entry visited is printed first
+                for (_, _, (entry, name)) in contents.into_iter().rev() {
+                    stack.push((entry, path.join(name), depth + 1));
+                }
+                continue;
+            }
+            Entry::Node(index, _) => {
+                let node = tree.get(index);
+                (node.size, node.node_type == NodeType::Directory)
+            }
+            Entry::Special(size) => (size, false),
+        };
+
+        let shown = max_depth.is_none_or(|max_depth| depth <= max_depth) && (is_directory || options.all || depth == 0);
+        if shown {
+            writeln!(out, "{}\t{}", du_size(size, options), display_path(&path, escape))?;
+        }
+    }
+    Ok(())
+}
+
+// du (without -L) does not follow a symlink given as the path and only counts
+// the link itself, the blocks of the link if `path` is one
+pub fn root_link_size(path: &Path, size_mode: SizeMode) -> Option<u64> {
+    let metadata = fs::symlink_metadata(path).ok()?;
+    metadata.file_type().is_symlink().then(|| sizes(&metadata, size_mode).0)
+}
+
+// print the only line of a root that is not scanned, see `root_link_size`
+pub fn print_du_root(size: u64, root: &Path, options: &DuOptions, escape: bool) -> Result<(), String> {
+    let mut out = io::stdout().lock();
+    match writeln!(out, "{}\t{}", du_size(size, options), display_path(root, escape)) {
+        Err(error) if error.kind() != io::ErrorKind::BrokenPipe => Err(error.to_string()),
+        _ => Ok(()),
+    }
+}
+
+// sort the contents of a directory of `entries` entries, given as (position,
+// inode, _), into the order du visits them: the order they were listed in or
+// by inode like fts
+fn visit_order<T>(contents: &mut [(usize, u64, T)], entries: usize, fs_type: Option<&str>) {
+    let unsorted = fs_type.is_some_and(|fs_type| UNSORTED_FS_TYPES.contains(&fs_type));
+    if entries > INODE_SORT_THRESHOLD && !unsorted {
+        contents.sort_by_key(|(_, inode, _)| *inode);
+    } else {
+        contents.sort_by_key(|(position, _, _)| *position);
+    }
+}
+
+fn du_size(size: u64, options: &DuOptions) -> String {
+    if options.human_readable {
+        human_size(size)
+    } else {
+        blocks(size).to_string()
+    }
+}
+
+// du's default unit, 1024 byte blocks rounded up
+fn blocks(size: u64) -> u64 {
+    size.div_ceil(1024)
+}
+
+// du -h: values below 10 with one decimal, both rounded up, e.g. 4.0K or 12M
+fn human_size(size: u64) -> String {
+    const UNITS: [&str; 8] = ["K", "M", "G", "T", "P", "E", "Z", "Y"];
+    // tenths of the largest sizes do not fit into 64 bits
+    let size = u128::from(size);
+    if size < 1024 {
+        return size.to_string();
+    }
+    let mut unit = 0;
+    let mut scale: u128 = 1024;
+    while size.div_ceil(scale) > 1024 && unit + 1 < UNITS.len() {
+        unit += 1;
+        scale *= 1024;
+    }
+    // tenths of the unit, rounded up
+    let tenths = (size * 10).div_ceil(scale);
+    if tenths < 100 {
+        return format!("{}.{}{}", tenths / 10, tenths % 10, UNITS[unit]);
+    }
+    let whole = size.div_ceil(scale);
+    if whole >= 1024 && unit + 1 < UNITS.len() {
+        return format!("1.0{}", UNITS[unit + 1]);
+    }
+    format!("{}{}", whole, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dir_stat::scan_tree::{NodeDetails, ScanTree};
+    use dir_stat::types::{DirectoryAlias, Excluded};
+
+    #[test]
+    fn test_du_sizes() {
+        assert_eq!(blocks(0), 0);
+        assert_eq!(blocks(4096), 4);
+        assert_eq!(blocks(4097), 5);
+        assert_eq!(human_size(0), "0");
+        assert_eq!(human_size(512), "512");
+        assert_eq!(human_size(4096), "4.0K");
+        assert_eq!(human_size(10239), "10K");
+        assert_eq!(human_size(1536 * 1024), "1.5M");
+        assert_eq!(human_size(1024 * 1024), "1.0M");
+        assert_eq!(human_size(1024 * 1024 - 1), "1.0M");
+        assert_eq!(human_size(123 * 1024 * 1024 + 1), "124M");
+        assert_eq!(human_size(u64::MAX), "16E");
+    }
+
+    #[test]
+    fn test_visit_order() {
+        let listed = || vec![(0, 30, "c"), (1, 10, "a"), (2, 20, "b")];
+        let visited = |entries: usize, fs_type: Option<&str>| {
+            let mut contents = listed();
+            visit_order(&mut contents, entries, fs_type);
+            contents.into_iter().map(|(_, _, name)| name).collect::<Vec<&str>>()
+        };
+        assert_eq!(visited(3, Some("ext4")), vec!["c", "a", "b"]);
+        assert_eq!(visited(10000, None), vec!["c", "a", "b"]);
+        assert_eq!(visited(10001, Some("ext4")), vec!["a", "b", "c"]);
+        assert_eq!(visited(10001, None), vec!["a", "b", "c"]);
+        assert_eq!(visited(10001, Some("tmpfs")), vec!["c", "a", "b"]);
+        assert_eq!(visited(10001, Some("nfs4")), vec!["c", "a", "b"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_root_link_size() {
+        let root = std::env::temp_dir().join(format!("dir-stat-du-link-{}", std::process::id()));
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::write(root.join("target/file"), vec![b'x'; 100000]).unwrap();
+        let link = root.join("link");
+        let _ = fs::remove_file(&link);
+        std::os::unix::fs::symlink(root.join("target"), &link).unwrap();
+
+        let link_size = fs::symlink_metadata(&link).unwrap().len();
+        assert_eq!(root_link_size(&link, SizeMode::Apparent), Some(link_size));
+        assert!(root_link_size(&link, SizeMode::Allocated).unwrap() < 100000);
+        assert_eq!(root_link_size(&root.join("target"), SizeMode::Allocated), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_write_du() {
+        let mut tree = ScanTree::new(PathBuf::from("/scan"));
+        tree.record_details();
+        let root = tree.root();
+        let listed = |tree: &mut ScanTree, parent, name: &str, node_type, size, position| {
+            let index = tree.add_node(parent, OsStr::new(name), node_type, size, None);
+            tree.describe(index, NodeDetails { position, ..NodeDetails::default() });
+            index
+        };
+        tree.add_size(root, 4096, None);
+        let b = listed(&mut tree, root, "b", NodeType::Directory, 0, 2);
+        tree.add_size(b, 4096, None);
+        listed(&mut tree, b, "f", NodeType::File, 2048, 0);
+        listed(&mut tree, root, "alias", NodeType::Directory, 0, 1);
+        listed(&mut tree, root, "a", NodeType::File, 1024, 0);
+        // counted special files are part of the size of their directory
+        tree.add_size(root, 512, None);
+        tree.compute_totals();
+        let fifo = SpecialEntry { path: PathBuf::from("/scan/fifo"), id: (0, 0), position: 3, size: 512, apparent_size: None };
+        let alias = DirectoryAlias { path: PathBuf::from("/scan/alias"), target: PathBuf::from("/scan/b"), is_loop: false };
+        let scan = ScanResult {
+            tree,
+            double_count: HashMap::new(),
+            hardlinks: Vec::new(),
+            sym_links: Vec::new(),
+            skipped_mounts: Vec::new(),
+            directory_aliases: vec![alias],
+            devices: Vec::new(),
+            excluded: Excluded::default(),
+            special_files: Vec::new(),
+            counted_special: vec![(root, fifo)],
+            errors: Vec::new(),
+        };
+
+        let du = |all: bool, summarize: bool, max_depth: Option<usize>| {
+            let options = DuOptions { all, summarize, human_readable: false, max_depth };
+            let mut out = Vec::new();
+            write_du(&mut out, &scan, Path::new("given"), &options, false).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        // entries in listing order, directories after their contents and
+        // without the directory listed again
+        assert_eq!(du(true, false, None), "1\tgiven/a\n2\tgiven/b/f\n6\tgiven/b\n1\tgiven/fifo\n12\tgiven\n");
+        assert_eq!(du(false, false, None), "6\tgiven/b\n12\tgiven\n");
+        assert_eq!(du(true, false, Some(1)), "1\tgiven/a\n6\tgiven/b\n1\tgiven/fifo\n12\tgiven\n");
+        assert_eq!(du(true, true, None), "12\tgiven\n");
+    }
+}
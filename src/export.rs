@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+
+use dir_stat::scan_tree::TreeNode;
+use dir_stat::types::{Column, NodeType, ScanResult};
+
+use crate::utils::display_path;
+
+// one row per node of the tree, parents before their children, fields are
+// quoted when they contain the delimiter, quotes or line breaks
+pub fn write_table<W: Write>(out: W, scan: &ScanResult, columns: &[Column], delimiter: u8, escape: bool) -> Result<(), String> {
+    let mut writer = csv::WriterBuilder::new().delimiter(delimiter).from_writer(out);
+    let to_string = |error: csv::Error| error.to_string();
+
+    writer
+        .write_record(columns.iter().map(|column| column.name()))
+        .map_err(to_string)?;
+    let owners = if columns.contains(&Column::Owner) { user_names(scan) } else { HashMap::new() };
+    for (index, node) in scan.tree.iter() {
+        let row = columns.iter().map(|column| field(scan, index, node, *column, &owners, escape));
+        writer.write_record(row).map_err(to_string)?;
+    }
+    writer.flush().map_err(|error| error.to_string())
+}
+
+fn field(scan: &ScanResult, index: usize, node: &TreeNode, column: Column, owners: &HashMap<u32, String>, escape: bool) -> String {
+    let optional = |value: Option<u64>| value.map(|value| value.to_string()).unwrap_or_default();
+    let attributes = scan.tree.details(index).map(|details| details.attributes).unwrap_or_default();
+    match column {
+        Column::Path => display_path(&scan.tree.path(index), escape).into_owned(),
+        Column::Name => display_path(Path::new(&node.name), escape).into_owned(),
+        Column::Type => String::from(if node.node_type == NodeType::File { "file" } else { "directory" }),
+        Column::Size => node.size.to_string(),
+        Column::ApparentSize => optional(node.apparent_size),
+        Column::ExclusiveSize => node.exclusive_size.to_string(),
+        Column::Files => node.files.to_string(),
+        Column::Depth => node.depth.to_string(),
+        Column::Mtime => attributes.modified.map(format_timestamp).unwrap_or_default(),
+        Column::Owner => match attributes.owner {
+            Some(uid) => owners.get(&uid).cloned().unwrap_or_else(|| uid.to_string()),
+            None => String::new(),
+        },
+    }
+}
+
+// names of the owners of the nodes, looked up once per id in the user
+// database of the system (which includes directory services like LDAP),
+// unknown ids are shown as numbers
+fn user_names(scan: &ScanResult) -> HashMap<u32, String> {
+    let mut names = HashMap::new();
+    for (index, _) in scan.tree.iter() {
+        if let Some(uid) = scan.tree.details(index).and_then(|details| details.attributes.owner) {
+            if let std::collections::hash_map::Entry::Vacant(entry) = names.entry(uid) {
+                if let Some(name) = user_name(uid) {
+                    entry.insert(name);
+                }
+            }
+        }
+    }
+    names
+}
+
+#[cfg(unix)]
+fn user_name(uid: u32) -> Option<String> {
+    let mut buffer: Vec<libc::c_char> = vec![0; 1024];
+    loop {
+        // SAFETY: all pointers refer to live locals, `buffer.len()` is the
+        // size of the buffer and on success `pw_name` points into the buffer
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result: *mut libc::passwd = std::ptr::null_mut();
+        let status = unsafe { libc::getpwuid_r(uid, &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+        // the buffer is too small for a large entry
+        if status == libc::ERANGE && buffer.len() < 1 << 20 {
+            buffer.resize(buffer.len() * 2, 0);
+            continue;
+        }
+        if status != 0 || result.is_null() {
+            return None;
+        }
+        let name = unsafe { std::ffi::CStr::from_ptr(passwd.pw_name) };
+        return Some(name.to_string_lossy().into_owned());
+    }
+}
+
+#[cfg(not(unix))]
+fn user_name(_uid: u32) -> Option<String> {
+    None
+}
+
+// seconds since the unix epoch as an ISO 8601 UTC timestamp
+fn format_timestamp(seconds: i64) -> String {
+    let (days, time) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+
+    // civil date from days since 1970-01-01, see Howard Hinnant's date algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::OsStr;
+    use std::path::PathBuf;
+
+    use dir_stat::scan_tree::{NodeDetails, ScanTree};
+    use dir_stat::types::{Excluded, NodeAttributes};
+
+    #[cfg(unix)]
+    #[test]
+    fn test_user_name() {
+        assert_eq!(user_name(0).as_deref(), Some("root"));
+        assert_eq!(user_name(u32::MAX - 1), None);
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_timestamp(1700000000), "2023-11-14T22:13:20Z");
+        assert_eq!(format_timestamp(-1), "1969-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn test_write_table() {
+        let mut tree = ScanTree::new(PathBuf::from("/root"));
+        tree.record_details();
+        let root = tree.root();
+        let dir = tree.add_node(root, OsStr::new("a,b"), NodeType::Directory, 4096, Some(100));
+        tree.add_node(dir, OsStr::new("say \"hi\""), NodeType::File, 8192, Some(5000));
+        let modified = NodeAttributes { modified: Some(0), owner: None };
+        tree.describe(dir, NodeDetails { attributes: modified, ..NodeDetails::default() });
+        tree.compute_totals();
+        let scan = ScanResult {
+            tree,
+            double_count: HashMap::new(),
+            hardlinks: Vec::new(),
+            sym_links: Vec::new(),
+            skipped_mounts: Vec::new(),
+            directory_aliases: Vec::new(),
+            devices: Vec::new(),
+            excluded: Excluded::default(),
+            special_files: Vec::new(),
+            counted_special: Vec::new(),
+            errors: Vec::new(),
+        };
+
+        let table = |columns: &[Column], delimiter: u8| {
+            let mut out = Vec::new();
+            write_table(&mut out, &scan, columns, delimiter, false).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        // parents come before their children, fields holding the delimiter
+        // or quotes are quoted
+        let columns = [Column::Path, Column::Type, Column::Size, Column::ApparentSize, Column::Files, Column::Depth, Column::Mtime];
+        assert_eq!(
+            table(&columns, b','),
+            "path,type,size,apparent-size,files,depth,mtime\n\
+             /root,directory,12288,5100,1,0,\n\
+             \"/root/a,b\",directory,12288,5100,1,1,1970-01-01T00:00:00Z\n\
+             \"/root/a,b/say \"\"hi\"\"\",file,8192,5000,1,2,\n"
+        );
+        assert_eq!(table(&[Column::Name, Column::ExclusiveSize], b'\t'), "name\texclusive-size\n/root\t0\na,b\t12288\n\"say \"\"hi\"\"\"\t8192\n");
+    }
+}
@@ -1,13 +1,19 @@
 use std::fs::Metadata;
 
+use crate::types::SizeMode;
+
 #[cfg(unix)]
 mod implementation {
     use super::*;
 
     use std::os::unix::fs::MetadataExt;
 
-    pub fn file_size(metadata: &Metadata) -> u128 {
-        u128::from(metadata.blocks()) * 512
+    pub fn file_size(metadata: &Metadata) -> u64 {
+        metadata.blocks() * 512
+    }
+
+    pub fn apparent_size(metadata: &Metadata) -> u64 {
+        metadata.size()
     }
 }
 
@@ -17,8 +23,12 @@ mod implementation {
 
     use std::os::windows::fs::MetadataExt;
 
-    pub fn file_size(metadata: &Metadata) -> u128 {
-        u128::from(metadata.size())
+    pub fn file_size(metadata: &Metadata) -> u64 {
+        metadata.file_size()
+    }
+
+    pub fn apparent_size(metadata: &Metadata) -> u64 {
+        metadata.file_size()
     }
 }
 
@@ -26,11 +36,61 @@ mod implementation {
 mod implementation {
     use super::*;
 
-    pub fn file_size(metadata: &Metadata) -> u128 {
-        u128::from(metadata.len())
+    pub fn file_size(metadata: &Metadata) -> u64 {
+        metadata.len()
+    }
+
+    pub fn apparent_size(metadata: &Metadata) -> u64 {
+        metadata.len()
     }
 }
 
-pub fn file_size(metadata: &Metadata) -> u128 {
+// size allocated on disk for the node
+pub fn file_size(metadata: &Metadata) -> u64 {
     self::implementation::file_size(metadata)
 }
+
+// logical length of the node in bytes
+pub fn apparent_size(metadata: &Metadata) -> u64 {
+    self::implementation::apparent_size(metadata)
+}
+
+// sizes of a node as selected by the size mode, see `FileInfo`
+pub fn sizes(metadata: &Metadata, mode: SizeMode) -> (u64, Option<u64>) {
+    match mode {
+        SizeMode::Allocated => (file_size(metadata), None),
+        SizeMode::Apparent => (apparent_size(metadata), None),
+        SizeMode::Both => (file_size(metadata), Some(apparent_size(metadata))),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::test_support::Fixture;
+
+    #[test]
+    fn test_size_modes() {
+        let fixture = Fixture::new("sizes");
+        // a sparse file has a length but hardly any blocks
+        fixture.dir("dir");
+        let sparse = fs::File::create(fixture.path("dir/sparse")).unwrap();
+        sparse.set_len(1 << 20).unwrap();
+        let metadata = fs::symlink_metadata(fixture.path("dir/sparse")).unwrap();
+
+        let (allocated, _) = sizes(&metadata, SizeMode::Allocated);
+        assert!(allocated < 1 << 20);
+        assert_eq!(sizes(&metadata, SizeMode::Apparent), (1 << 20, None));
+        assert_eq!(sizes(&metadata, SizeMode::Both), (allocated, Some(1 << 20)));
+
+        fixture.file("dir/dense", 10000);
+        let metadata = fs::symlink_metadata(fixture.path("dir/dense")).unwrap();
+        // whole blocks are allocated
+        assert!(file_size(&metadata) >= 10000);
+        assert_eq!(file_size(&metadata) % 512, 0);
+        assert_eq!(apparent_size(&metadata), 10000);
+    }
+}
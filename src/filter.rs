@@ -0,0 +1,161 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+
+// exclude and include glob patterns evaluated while walking, a pattern without
+// a `/` matches the file name of an entry anywhere below the root while a
+// pattern with a `/` matches the path relative to the root (`*` does not
+// cross directories there, `**` does)
+//
+// an entry matching an include pattern is always kept, otherwise an entry
+// matching an exclude pattern is pruned together with everything below it,
+// otherwise files (not directories) are pruned as soon as any include
+// pattern is given
+//
+// a filter is (de)serialized as the patterns it was built from
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(try_from = "FilterPatterns", into = "FilterPatterns")]
+pub struct PathFilter {
+    excludes: Patterns,
+    includes: Patterns,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Patterns {
+    names: GlobSet,
+    paths: GlobSet,
+    patterns: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FilterPatterns {
+    exclude: Vec<String>,
+    include: Vec<String>,
+}
+
+impl TryFrom<FilterPatterns> for PathFilter {
+    type Error = globset::Error;
+
+    fn try_from(patterns: FilterPatterns) -> Result<PathFilter, globset::Error> {
+        PathFilter::new(&patterns.exclude, &patterns.include)
+    }
+}
+
+impl From<PathFilter> for FilterPatterns {
+    fn from(filter: PathFilter) -> FilterPatterns {
+        FilterPatterns {
+            exclude: filter.excludes.patterns,
+            include: filter.includes.patterns,
+        }
+    }
+}
+
+impl Patterns {
+    fn new(patterns: &[String]) -> Result<Patterns, globset::Error> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+        for pattern in patterns.iter() {
+            if pattern.contains('/') {
+                let pattern = pattern.trim_start_matches('/').trim_end_matches('/');
+                paths.add(GlobBuilder::new(pattern).literal_separator(true).build()?);
+            } else {
+                names.add(Glob::new(pattern)?);
+            }
+        }
+        Ok(Patterns {
+            names: names.build()?,
+            paths: paths.build()?,
+            patterns: patterns.to_vec(),
+        })
+    }
+
+    fn matches(&self, relative_path: &Path) -> bool {
+        if self.patterns.is_empty() {
+            return false;
+        }
+        relative_path.file_name().is_some_and(|name| self.names.is_match(name)) || self.paths.is_match(relative_path)
+    }
+}
+
+impl PathFilter {
+    pub fn new(excludes: &[String], includes: &[String]) -> Result<PathFilter, globset::Error> {
+        Ok(PathFilter {
+            excludes: Patterns::new(excludes)?,
+            includes: Patterns::new(includes)?,
+        })
+    }
+
+    pub fn exclude_patterns(&self) -> &[String] {
+        &self.excludes.patterns
+    }
+
+    pub fn include_patterns(&self) -> &[String] {
+        &self.includes.patterns
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.excludes.patterns.is_empty() && self.includes.patterns.is_empty()
+    }
+
+    // whether the entry at `relative_path` (relative to the scan root) is pruned
+    pub fn excludes(&self, relative_path: &Path, is_dir: bool) -> bool {
+        if self.includes.matches(relative_path) {
+            return false;
+        }
+        self.excludes.matches(relative_path) || (!is_dir && !self.includes.patterns.is_empty())
+    }
+}
+
+// patterns of an exclude file, one per line, blank lines and lines starting
+// with `#` are ignored
+pub fn read_patterns(path: &Path) -> io::Result<Vec<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|pattern| pattern.to_string()).collect()
+    }
+
+    #[test]
+    fn test_excludes() {
+        let filter = PathFilter::new(&patterns(&[".git", "*.log", "/build/*/cache"]), &[]).unwrap();
+        assert!(filter.excludes(Path::new(".git"), true));
+        assert!(filter.excludes(Path::new("src/sub/.git"), true));
+        assert!(filter.excludes(Path::new("logs/run.log"), false));
+        assert!(filter.excludes(Path::new("build/debug/cache"), true));
+        assert!(!filter.excludes(Path::new("build/debug/deps/cache"), true));
+        assert!(!filter.excludes(Path::new("src/main.rs"), false));
+    }
+
+    #[test]
+    fn test_includes() {
+        let filter = PathFilter::new(&patterns(&["target"]), &patterns(&["*.rs"])).unwrap();
+        assert!(filter.excludes(Path::new("target"), true));
+        assert!(!filter.excludes(Path::new("src"), true));
+        assert!(!filter.excludes(Path::new("src/main.rs"), false));
+        assert!(filter.excludes(Path::new("README.md"), false));
+        assert!(!PathFilter::default().excludes(Path::new("README.md"), false));
+    }
+
+    #[test]
+    fn test_serialize_patterns() {
+        let filter = PathFilter::new(&patterns(&["target"]), &patterns(&["*.rs"])).unwrap();
+        let json = serde_json::to_string(&filter).unwrap();
+        assert_eq!(json, r#"{"exclude":["target"],"include":["*.rs"]}"#);
+        let filter: PathFilter = serde_json::from_str(&json).unwrap();
+        assert!(filter.excludes(Path::new("target"), true));
+        assert!(serde_json::from_str::<PathFilter>(r#"{"exclude":["a{"],"include":[]}"#).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::types::{FileInfo, HardlinkGroup, HardlinkPolicy, NodeId};
+
+// share of a hardlinked file credited to one of its paths
+pub struct Credit {
+    pub size: u64,
+    pub apparent_size: Option<u64>,
+
+    // the path the inode is attributed to, every other path is reported as
+    // double counted
+    pub primary: bool,
+}
+
+// decide for every path of an inode that is linked more than once within the
+// scan how much of its size is credited to that path, the decision only
+// depends on the set of paths so repeated scans of a tree always agree,
+// `traversal_order` gives the positions from the root down to a file for
+// `HardlinkPolicy::Traversal`
+pub fn attribute<'a>(
+    files: impl Iterator<Item = &'a FileInfo>,
+    policy: HardlinkPolicy,
+    traversal_order: impl Fn(&FileInfo) -> Vec<usize>,
+) -> (HashMap<PathBuf, Credit>, Vec<HardlinkGroup>) {
+    // only files with other links somewhere on their file system can have
+    // another path inside the scan
+    let mut groups: HashMap<NodeId, Vec<&FileInfo>> = HashMap::new();
+    for file in files.filter(|file| file.links > 1) {
+        groups.entry(file.node.id).or_default().push(file);
+    }
+    groups.retain(|_, group| group.len() > 1);
+    if groups.is_empty() {
+        return (HashMap::new(), Vec::new());
+    }
+
+    let mut credits: HashMap<PathBuf, Credit> = HashMap::new();
+    let mut hardlinks: Vec<HardlinkGroup> = Vec::with_capacity(groups.len());
+    for (id, mut group) in groups {
+        match policy {
+            HardlinkPolicy::Shallowest => group.sort_by(|a, b| {
+                depth(&a.node.path)
+                    .cmp(&depth(&b.node.path))
+                    .then_with(|| a.node.path.cmp(&b.node.path))
+            }),
+            HardlinkPolicy::Traversal => group.sort_by_cached_key(|file| traversal_order(file)),
+            _ => group.sort_by(|a, b| a.node.path.cmp(&b.node.path)),
+        }
+        hardlinks.push(HardlinkGroup {
+            id,
+            paths: group.iter().map(|file| file.node.path.clone()).collect(),
+            size: group[0].size,
+            apparent_size: group[0].apparent_size,
+        });
+        let count = group.len() as u64;
+        for (position, file) in group.into_iter().enumerate() {
+            let position = position as u64;
+            let primary = position == 0;
+            let (size, apparent_size) = match policy {
+                HardlinkPolicy::First | HardlinkPolicy::Shallowest | HardlinkPolicy::Traversal => {
+                    if primary {
+                        (file.size, file.apparent_size)
+                    } else {
+                        (0, file.apparent_size.map(|_| 0))
+                    }
+                }
+                HardlinkPolicy::Split => (
+                    share(file.size, count, position),
+                    file.apparent_size.map(|size| share(size, count, position)),
+                ),
+                HardlinkPolicy::Everywhere => (file.size, file.apparent_size),
+            };
+            credits.insert(file.node.path.clone(), Credit { size, apparent_size, primary });
+        }
+    }
+    hardlinks.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.paths.cmp(&b.paths)));
+    (credits, hardlinks)
+}
+
+fn depth(path: &Path) -> usize {
+    path.components().count()
+}
+
+// split `size` into `count` parts that add up to exactly `size`, the
+// remainder goes to the first paths
+fn share(size: u64, count: u64, position: u64) -> u64 {
+    size / count + u64::from(position < size % count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::sample;
+    use crate::types::{NodeAttributes, NodeInfo, ScanOptions, ScanResult};
+    use crate::walk_async::ThreadedWalker;
+    use crate::walker::scan;
+
+    #[test]
+    fn test_share() {
+        let parts: Vec<u64> = (0..3).map(|position| share(10, 3, position)).collect();
+        assert_eq!(parts, vec![4, 3, 3]);
+        assert_eq!(parts.iter().sum::<u64>(), 10);
+        assert_eq!(share(4096, 2, 1), 2048);
+    }
+
+    #[test]
+    fn test_attribute_without_links() {
+        let file = |path: &str, id: u64, links: u64| FileInfo {
+            size: 4096,
+            apparent_size: None,
+            links,
+            node: NodeInfo {
+                path: PathBuf::from(path),
+                id: (1, id),
+                position: 0,
+                attributes: NodeAttributes::default(),
+            },
+        };
+        let no_order = |_: &FileInfo| -> Vec<usize> { unreachable!() };
+        let single = [file("/a", 1, 1), file("/b", 2, 1)];
+        let (credits, hardlinks) = attribute(single.iter(), HardlinkPolicy::Traversal, no_order);
+        assert!(credits.is_empty() && hardlinks.is_empty());
+
+        // a link outside of the scan does not make a group either
+        let linked = [file("/a", 1, 2), file("/b", 2, 2), file("/c", 2, 2)];
+        let (credits, hardlinks) = attribute(linked.iter(), HardlinkPolicy::First, no_order);
+        assert_eq!(hardlinks.len(), 1);
+        assert_eq!(hardlinks[0].paths, vec![PathBuf::from("/b"), PathBuf::from("/c")]);
+        assert!(credits[Path::new("/b")].primary && !credits.contains_key(Path::new("/a")));
+    }
+
+    #[test]
+    fn test_hardlink_policies() {
+        let fixture = sample();
+        let size_of = |scan: &ScanResult, relative: &str| {
+            scan.tree.find(&fixture.path(relative)).map(|index| scan.tree.get(index).size)
+        };
+        let scan_with = |hardlink_policy: HardlinkPolicy| {
+            let options = ScanOptions { hardlink_policy, ..ScanOptions::default() };
+            scan(&ThreadedWalker { threads: 4 }, fixture.root.clone(), &options).unwrap()
+        };
+        let full = scan_with(HardlinkPolicy::Everywhere);
+        let two = size_of(&full, "a/b/two").unwrap();
+        assert!(two > 0);
+        assert_eq!(size_of(&full, "d/two-link"), Some(two));
+
+        let first = scan_with(HardlinkPolicy::First);
+        assert_eq!(size_of(&first, "a/b/two"), Some(two));
+        assert_eq!(size_of(&first, "d/two-link"), None);
+        assert!(first.double_count.contains_key(&fixture.path("d/two-link")));
+        let group = first
+            .hardlinks
+            .iter()
+            .find(|group| group.paths.contains(&fixture.path("d/two-link")))
+            .unwrap();
+        assert_eq!(group.links(), 2);
+        assert_eq!(group.credited(), &fixture.path("a/b/two"));
+        assert_eq!(group.size, two);
+
+        let shallowest = scan_with(HardlinkPolicy::Shallowest);
+        assert_eq!(size_of(&shallowest, "a/b/two"), None);
+        assert_eq!(size_of(&shallowest, "d/two-link"), Some(two));
+
+        let split = scan_with(HardlinkPolicy::Split);
+        assert_eq!(
+            size_of(&split, "a/b/two").unwrap() + size_of(&split, "d/two-link").unwrap(),
+            two
+        );
+
+        // which path is reached first depends on the listing order
+        let traversal = scan_with(HardlinkPolicy::Traversal);
+        let credited: Vec<Option<u64>> = vec![size_of(&traversal, "a/b/two"), size_of(&traversal, "d/two-link")];
+        assert_eq!(credited.iter().filter(|size| size.is_some()).count(), 1);
+
+        // only the policies that count a file once agree on the total
+        let total = |scan: &ScanResult| scan.tree.get(scan.tree.root()).size;
+        assert_eq!(total(&first), total(&shallowest));
+        assert_eq!(total(&first), total(&traversal));
+        assert_eq!(total(&first), total(&split));
+        assert!(total(&full) > total(&first));
+    }
+}
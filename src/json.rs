@@ -0,0 +1,376 @@
+// JSON document describing a scan, a stable schema for scripts decoupled from
+// the internal types, changes to it bump `SCHEMA_VERSION`
+//
+// version 1:
+//
+//     schema_version     always 1
+//     metadata           tool, version, root, runtime_seconds, options,
+//                        total_size, total_apparent_size, files,
+//                        directories and unreadable_directories
+//       options          error_policy, size_mode, hardlink_policy,
+//                        follow_symlinks (named like the command line
+//                        values), one_file_system, skip_fs_types, filter
+//                        (exclude and include patterns), measure_excluded
+//                        and count_special
+//     nodes              every node of the tree, parents before children:
+//                        index, parent (null for the root), path, name, type
+//                        ("file" or "directory"), depth, size, apparent_size,
+//                        exclusive_size and exclusive_apparent_size
+//     double_counted     paths of hardlinked files not credited with the
+//                        file: path, size, apparent_size
+//     hardlinks          files linked more than once: device, inode, size,
+//                        apparent_size, credited (path) and paths
+//     symlinks           path, target and status ("internal", "external",
+//                        "ancestor", "broken" or "loop")
+//     skipped_mounts     path and fs_type
+//     directory_aliases  path, target and loop
+//     devices            device, mount_point, fs_type, size, apparent_size
+//                        and files
+//     excluded           entries, size and apparent_size (null unless
+//                        measured)
+//     special_files      directory, sockets, fifos, block_devices,
+//                        char_devices, other and size
+//     errors             path, operation ("read-dir", "read-entry" or
+//                        "metadata"), kind and message
+//
+// sizes are in bytes and unsigned 64 bit integers, which javascript only
+// reads exactly up to 2^53 (8 PiB), apparent sizes are null unless recorded
+// by the size mode, paths that are not valid UTF-8 have invalid bytes
+// replaced by U+FFFD or, with `--escape`, are escaped like in the text output
+use std::path::Path;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::display::display_path;
+use crate::scan_tree::NodeIndex;
+use crate::types::{ErrorPolicy, FollowSymlinks, HardlinkPolicy, NodeType, ScanOperation, ScanOptions, ScanResult, SizeMode, SymLinkStatus};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+pub struct JsonReport {
+    pub schema_version: u32,
+    pub metadata: Metadata,
+    pub nodes: Vec<Node>,
+    pub double_counted: Vec<DoubleCounted>,
+    pub hardlinks: Vec<Hardlinks>,
+    pub symlinks: Vec<Symlink>,
+    pub skipped_mounts: Vec<SkippedMount>,
+    pub directory_aliases: Vec<DirectoryAlias>,
+    pub devices: Vec<Device>,
+    pub excluded: Excluded,
+    pub special_files: Vec<SpecialFiles>,
+    pub errors: Vec<Error>,
+}
+
+#[derive(Serialize)]
+pub struct Metadata {
+    pub tool: &'static str,
+    pub version: &'static str,
+    pub root: String,
+    pub runtime_seconds: f64,
+    pub options: Options,
+    pub total_size: u64,
+    pub total_apparent_size: Option<u64>,
+    pub files: usize,
+    pub directories: usize,
+    pub unreadable_directories: usize,
+}
+
+// the scan options that shape the result
+#[derive(Serialize)]
+pub struct Options {
+    pub error_policy: ErrorPolicy,
+    pub size_mode: SizeMode,
+    pub hardlink_policy: HardlinkPolicy,
+    pub follow_symlinks: FollowSymlinks,
+    pub one_file_system: bool,
+    pub skip_fs_types: Vec<String>,
+    pub filter: Filter,
+    pub measure_excluded: bool,
+    pub count_special: bool,
+}
+
+#[derive(Serialize)]
+pub struct Filter {
+    pub exclude: Vec<String>,
+    pub include: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct Node {
+    pub index: NodeIndex,
+    pub parent: Option<NodeIndex>,
+    pub path: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub node_type: NodeType,
+    pub depth: usize,
+    pub size: u64,
+    pub apparent_size: Option<u64>,
+    pub exclusive_size: u64,
+    pub exclusive_apparent_size: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct DoubleCounted {
+    pub path: String,
+    pub size: u64,
+    pub apparent_size: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct Hardlinks {
+    pub device: u64,
+    pub inode: u64,
+    pub size: u64,
+    pub apparent_size: Option<u64>,
+    pub credited: String,
+    pub paths: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct Symlink {
+    pub path: String,
+    pub target: String,
+    pub status: SymLinkStatus,
+}
+
+#[derive(Serialize)]
+pub struct SkippedMount {
+    pub path: String,
+    pub fs_type: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct DirectoryAlias {
+    pub path: String,
+    pub target: String,
+    #[serde(rename = "loop")]
+    pub is_loop: bool,
+}
+
+#[derive(Serialize)]
+pub struct Device {
+    pub device: u64,
+    pub mount_point: Option<String>,
+    pub fs_type: Option<String>,
+    pub size: u64,
+    pub apparent_size: Option<u64>,
+    pub files: usize,
+}
+
+#[derive(Serialize)]
+pub struct Excluded {
+    pub entries: usize,
+    pub size: Option<u64>,
+    pub apparent_size: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct SpecialFiles {
+    pub directory: String,
+    pub sockets: usize,
+    pub fifos: usize,
+    pub block_devices: usize,
+    pub char_devices: usize,
+    pub other: usize,
+    pub size: u64,
+}
+
+#[derive(Serialize)]
+pub struct Error {
+    pub path: String,
+    pub operation: ScanOperation,
+    pub kind: String,
+    pub message: String,
+}
+
+impl JsonReport {
+    // paths are escaped like in the text output with `escape`
+    pub fn new(scan: &ScanResult, options: &ScanOptions, runtime: Duration, escape: bool) -> JsonReport {
+        let lossy = |path: &Path| display_path(path, escape).into_owned();
+        let tree = &scan.tree;
+        let root = tree.get(tree.root());
+        let files = tree.iter().filter(|(_, node)| node.node_type == NodeType::File).count();
+
+        let mut double_counted: Vec<DoubleCounted> = scan
+            .double_count
+            .iter()
+            .map(|(path, result)| DoubleCounted {
+                path: lossy(path),
+                size: result.size,
+                apparent_size: result.apparent_size,
+            })
+            .collect();
+        double_counted.sort_by(|a, b| a.path.cmp(&b.path));
+
+        JsonReport {
+            schema_version: SCHEMA_VERSION,
+            metadata: Metadata {
+                tool: env!("CARGO_PKG_NAME"),
+                version: env!("CARGO_PKG_VERSION"),
+                root: lossy(&tree.path(tree.root())),
+                runtime_seconds: runtime.as_secs_f64(),
+                options: Options {
+                    error_policy: options.error_policy,
+                    size_mode: options.size_mode,
+                    hardlink_policy: options.hardlink_policy,
+                    follow_symlinks: options.follow_symlinks,
+                    one_file_system: options.one_file_system,
+                    skip_fs_types: options.skip_fs_types.clone(),
+                    filter: Filter {
+                        exclude: options.filter.exclude_patterns().to_vec(),
+                        include: options.filter.include_patterns().to_vec(),
+                    },
+                    measure_excluded: options.measure_excluded,
+                    count_special: options.count_special,
+                },
+                total_size: root.size,
+                total_apparent_size: root.apparent_size,
+                files,
+                directories: tree.len() - files,
+                unreadable_directories: scan.unreadable_directories(),
+            },
+            nodes: tree
+                .iter()
+                .map(|(index, node)| Node {
+                    index,
+                    parent: node.parent,
+                    path: lossy(&tree.path(index)),
+                    name: lossy(Path::new(&node.name)),
+                    node_type: node.node_type,
+                    depth: node.depth,
+                    size: node.size,
+                    apparent_size: node.apparent_size,
+                    exclusive_size: node.exclusive_size,
+                    exclusive_apparent_size: node.exclusive_apparent_size,
+                })
+                .collect(),
+            double_counted,
+            hardlinks: scan
+                .hardlinks
+                .iter()
+                .map(|group| Hardlinks {
+                    device: group.id.0,
+                    inode: group.id.1,
+                    size: group.size,
+                    apparent_size: group.apparent_size,
+                    credited: lossy(group.credited()),
+                    paths: group.paths.iter().map(|path| lossy(path)).collect(),
+                })
+                .collect(),
+            symlinks: scan
+                .sym_links
+                .iter()
+                .map(|link| Symlink {
+                    path: lossy(&link.path),
+                    target: lossy(&link.target),
+                    status: link.status,
+                })
+                .collect(),
+            skipped_mounts: scan
+                .skipped_mounts
+                .iter()
+                .map(|mount| SkippedMount {
+                    path: lossy(&mount.path),
+                    fs_type: mount.fs_type.clone(),
+                })
+                .collect(),
+            directory_aliases: scan
+                .directory_aliases
+                .iter()
+                .map(|alias| DirectoryAlias {
+                    path: lossy(&alias.path),
+                    target: lossy(&alias.target),
+                    is_loop: alias.is_loop,
+                })
+                .collect(),
+            devices: scan
+                .devices
+                .iter()
+                .map(|usage| Device {
+                    device: usage.device,
+                    mount_point: usage.mount_point.as_deref().map(&lossy),
+                    fs_type: usage.fs_type.clone(),
+                    size: usage.size,
+                    apparent_size: usage.apparent_size,
+                    files: usage.files,
+                })
+                .collect(),
+            excluded: Excluded {
+                entries: scan.excluded.entries,
+                size: scan.excluded.size,
+                apparent_size: scan.excluded.apparent_size,
+            },
+            special_files: scan
+                .special_files
+                .iter()
+                .map(|(directory, special_files)| SpecialFiles {
+                    directory: lossy(directory),
+                    sockets: special_files.sockets,
+                    fifos: special_files.fifos,
+                    block_devices: special_files.block_devices,
+                    char_devices: special_files.char_devices,
+                    other: special_files.other,
+                    size: special_files.size,
+                })
+                .collect(),
+            errors: scan
+                .errors
+                .iter()
+                .map(|error| Error {
+                    path: lossy(&error.path),
+                    operation: error.operation,
+                    kind: format!("{:?}", error.kind),
+                    message: error.to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::Fixture;
+    use crate::walk::process_dir;
+
+    #[test]
+    fn test_json_report() {
+        let fixture = Fixture::new("json");
+        fixture.file("a/one", 10).file("two", 5000).hard_link("two", "a/two-link").file("new\nline", 1);
+        let options = ScanOptions {
+            filter: crate::filter::PathFilter::new(&[String::from("*.log")], &[]).unwrap(),
+            ..ScanOptions::default()
+        };
+        let scan = process_dir(fixture.root.clone(), &options).unwrap();
+        let to_json = |escape: bool| -> serde_json::Value {
+            let report = JsonReport::new(&scan, &options, Duration::from_millis(5), escape);
+            serde_json::from_str(&serde_json::to_string(&report).unwrap()).unwrap()
+        };
+        let json = to_json(false);
+        let lossy = |path: &Path| path.to_string_lossy().into_owned();
+
+        assert_eq!(json["schema_version"], 1);
+        assert_eq!(json["metadata"]["files"], 3);
+        assert_eq!(json["metadata"]["options"]["filter"]["exclude"][0], "*.log");
+        assert_eq!(json["metadata"]["options"]["hardlink_policy"], "shallowest");
+        // internal switches are not part of the schema
+        assert!(json["metadata"]["options"].get("record_details").is_none());
+        assert_eq!(json["nodes"][0]["parent"], serde_json::Value::Null);
+        assert_eq!(json["nodes"][0]["type"], "directory");
+        assert_eq!(json["nodes"].as_array().unwrap().len(), scan.tree.len());
+        assert_eq!(json["double_counted"][0]["path"], lossy(&fixture.path("a/two-link")));
+        assert_eq!(json["hardlinks"][0]["credited"], lossy(&fixture.path("two")));
+
+        // paths are escaped like in the text output
+        let names = |json: &serde_json::Value| -> Vec<String> {
+            json["nodes"].as_array().unwrap().iter().map(|node| node["name"].as_str().unwrap().to_string()).collect()
+        };
+        assert!(names(&json).contains(&String::from("new\nline")));
+        assert!(names(&to_json(true)).contains(&String::from("new\\nline")));
+    }
+}
@@ -1,6 +1,18 @@
 mod process_directory;
-mod file_size;
+mod hardlink;
+mod node_id;
+mod symlink;
+#[cfg(test)]
+mod test_support;
 
+pub mod display;
+pub mod file_size;
+pub mod filter;
+pub mod json;
+pub mod mounts;
+pub mod scan_tree;
+pub mod stream;
 pub mod walk;
 pub mod walk_async;
-pub mod types;
\ No newline at end of file
+pub mod walker;
+pub mod types;
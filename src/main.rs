@@ -1,55 +1,266 @@
+pub mod du;
+pub mod export;
+pub mod report;
 pub mod utils;
 
 use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
 use std::time::Instant;
 use std::path::MAIN_SEPARATOR;
 use structopt::StructOpt;
 
-use dir_stat::walk::process_dir;
-use dir_stat::walk_async::process_dir_threaded;
-use dir_stat::types::NodeType;
-use crate::utils::to_decimal_prefix;
+use dir_stat::walk::{process_dir, SequentialWalker};
+use dir_stat::walk_async::{process_dir_threaded, ThreadedWalker};
+use dir_stat::filter::{read_patterns, PathFilter};
+use dir_stat::json::JsonReport;
+use dir_stat::stream::{stream, EventSink, StreamEvent};
+use dir_stat::mounts::{matches_fs_type, DEFAULT_SKIPPED_FS_TYPES};
+use dir_stat::scan_tree::{NodeIndex, TreeNode};
+use dir_stat::types::{Column, DirectoryRanking, ErrorPolicy, FollowSymlinks, HardlinkPolicy, NodeType, OutputFormat, ScanOptions, SizeMode};
+use crate::du::{print_du, print_du_root, root_link_size, DuOptions};
+use crate::export::write_table;
+use crate::report::{print_devices, print_directories, print_directory_aliases, print_excluded, print_hardlinks, print_skipped_mounts, print_special_files, print_sym_links, print_top_directories};
+use crate::utils::{display_path, display_size};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "dir-stat", about = "File and directory size analysis")]
 struct Opt {
     /// Path of the directory to start at.
-    path: String,
+    #[structopt(parse(from_os_str))]
+    path: PathBuf,
+
+    /// output format: text, json (a versioned document of the whole scan), ndjson (streamed while scanning), csv, tsv or du (the lines of du)
+    #[structopt(long, default_value = "text")]
+    format: OutputFormat,
+
+    /// columns of csv and tsv output: path, name, type, size, apparent-size, exclusive-size, files, depth, mtime, owner
+    #[structopt(long, use_delimiter = true, default_value = "path,type,size,apparent-size,files,depth")]
+    columns: Vec<Column>,
 
     /// Number of largest files to list.
     #[structopt(long, default_value = "30")]
     top: u8,
 
-    /// show total size of multiple-referenced files
+    /// list the largest directories instead of files, ranked by inclusive or exclusive (own files only) size
+    #[structopt(long)]
+    directories: Option<DirectoryRanking>,
+
+    /// list directories down to this depth below the root with everything deeper rolled up
+    #[structopt(short = "d", long)]
+    max_depth: Option<usize>,
+
+    /// du output: list files as well as directories
+    #[structopt(short = "a", long)]
+    all: bool,
+
+    /// du output: only print the total of the root
+    #[structopt(short = "s", long)]
+    summarize: bool,
+
+    /// du output: print sizes in powers of 1024 with a unit (K, M, G, ...)
+    #[structopt(short = "h", long)]
+    human_readable: bool,
+
+    /// show total size of multiple-referenced files and every path of the largest ones
     #[structopt(long)]
     hardlinks: bool,
 
+    /// which path of a hardlinked file is credited: first, traversal (first reached, as du does), shallowest, split or everywhere
+    #[structopt(long, default_value = "shallowest")]
+    hardlink_policy: HardlinkPolicy,
+
+    /// list broken, external and looping symlinks
+    #[structopt(long)]
+    symlinks: bool,
+
+    /// count sockets, fifos and device nodes by kind and list the directories holding them
+    #[structopt(long)]
+    special_files: bool,
+
+    /// stay on the file system of the root path
+    #[structopt(short = "x", long)]
+    one_file_system: bool,
+
+    /// split the totals by the device (mounted file system) the files live on
+    #[structopt(long)]
+    devices: bool,
+
+    /// skip mount points of this file system type (in addition to pseudo and network file systems)
+    #[structopt(long, number_of_values = 1)]
+    skip_fs_type: Vec<String>,
+
+    /// scan mount points of this file system type even if it is skipped by default
+    #[structopt(long, number_of_values = 1)]
+    include_fs_type: Vec<String>,
+
+    /// skip entries matching this glob, a pattern without `/` matches file names, one with `/` paths relative to the root
+    #[structopt(long, number_of_values = 1)]
+    exclude: Vec<String>,
+
+    /// read exclude patterns from a file, one per line
+    #[structopt(long, number_of_values = 1, parse(from_os_str))]
+    exclude_from: Vec<PathBuf>,
+
+    /// keep entries matching this glob even if excluded, once given only matching files are counted
+    #[structopt(long, number_of_values = 1)]
+    include: Vec<String>,
+
+    /// measure excluded entries and report their total on a single line
+    #[structopt(long)]
+    show_excluded: bool,
+
+    /// also count the blocks of unfollowed symlinks, sockets, fifos and device nodes, as du does
+    #[structopt(long)]
+    count_special: bool,
+
+    /// which symlinks to follow: never, root (only the given path) or always (every link
+    /// leading out of the root, links to nodes inside it are counted at their target only,
+    /// unix only), du output only follows a symlink given as the path with always, like du -L
+    #[structopt(long, default_value = "root")]
+    follow_symlinks: FollowSymlinks,
+
     /// number of threads to use
     #[structopt(long, default_value = "1")]
-    threads: u8
+    threads: u8,
+
+    /// what to do with unreadable directories and files: continue, fail-fast or warn
+    #[structopt(long, default_value = "warn")]
+    on_error: ErrorPolicy,
+
+    /// size to count: allocated (disk usage), apparent (logical length) or both
+    #[structopt(long, default_value = "allocated")]
+    size_mode: SizeMode,
+
+    /// escape non-UTF-8 bytes and control characters in paths instead of replacing them
+    #[structopt(long)]
+    escape: bool,
+}
+
+// default file system types to skip adjusted by the command line
+fn skipped_fs_types(skip: &[String], include: &[String]) -> Vec<String> {
+    let mut types: Vec<String> = DEFAULT_SKIPPED_FS_TYPES
+        .iter()
+        .map(|fs_type| fs_type.to_string())
+        .chain(skip.iter().cloned())
+        .collect();
+    types.retain(|fs_type| !matches_fs_type(fs_type, include));
+    types
+}
+
+// write one JSON object per line while the scan is running, the summary last
+fn stream_ndjson(root_path: PathBuf, threads: u8, options: &ScanOptions, escape: bool, start: Instant) -> Result<(), String> {
+    let write = |out: &mut BufWriter<io::Stdout>, event: &StreamEvent| {
+        // a closed pipe only ends the output, the scan itself is not aborted
+        let _ = serde_json::to_writer(&mut *out, event).and_then(|_| out.write_all(b"\n").map_err(serde_json::Error::io));
+    };
+    let mut out = BufWriter::new(io::stdout());
+    let sink: EventSink = Box::new(move |event: &StreamEvent| write(&mut out, event));
+    let result = if threads > 1 {
+        stream(&ThreadedWalker { threads }, root_path, options, escape, sink)
+    } else {
+        stream(&SequentialWalker, root_path, options, escape, sink)
+    }
+    .map_err(|error| error.to_string())?;
+
+    // the sink was dropped with the scan, which flushed everything before
+    let mut out = BufWriter::new(io::stdout());
+    write(&mut out, &result.summary(start.elapsed()));
+    match out.flush() {
+        Err(error) if error.kind() != io::ErrorKind::BrokenPipe => Err(error.to_string()),
+        _ => Ok(()),
+    }
 }
 
 fn main() -> Result<(), String> {
     let start = Instant::now();
     let opt = Opt::from_args();
 
-    let path = opt.path;
+    let path = opt.path.clone();
+    let du_options = DuOptions {
+        all: opt.all,
+        summarize: opt.summarize,
+        human_readable: opt.human_readable,
+        max_depth: opt.max_depth,
+    };
+    if opt.format == OutputFormat::Du && opt.follow_symlinks != FollowSymlinks::Always {
+        // like du without -L a symlink given as the path is not followed
+        if let Some(size) = root_link_size(&path, opt.size_mode) {
+            return print_du_root(size, &opt.path, &du_options, opt.escape);
+        }
+    }
+    if opt.follow_symlinks == FollowSymlinks::Never {
+        let link_metadata = fs::symlink_metadata(&path).expect("Failed to access path metadata");
+        if link_metadata.file_type().is_symlink() {
+            return Result::Err(String::from("Root path is a symlink and symlinks are not followed."));
+        }
+    }
     let canonicalized_path = fs::canonicalize(path).expect("Failed to canonalize path");
     // follow symlink to root path
-    let root_path_str = String::from(canonicalized_path.to_str().unwrap());
-    let path_metadata = fs::metadata(canonicalized_path).expect("Failed to access path metadata");
+    let path_metadata = fs::metadata(&canonicalized_path).expect("Failed to access path metadata");
     if !path_metadata.is_dir() {
         // do not proceed if targeting a non-directory
         return Result::Err(String::from("Root path is not a directory."));
     }
-    let scan = if opt.threads > 1 {
-        process_dir_threaded(root_path_str, opt.threads)
+    let mut excludes = opt.exclude.clone();
+    for pattern_file in opt.exclude_from.iter() {
+        let patterns = read_patterns(pattern_file)
+            .map_err(|error| format!("Failed to read exclude file {}: {}", pattern_file.display(), error))?;
+        excludes.extend(patterns);
+    }
+    let filter = PathFilter::new(&excludes, &opt.include).map_err(|error| error.to_string())?;
+
+    let options = ScanOptions {
+        error_policy: opt.on_error,
+        size_mode: opt.size_mode,
+        hardlink_policy: opt.hardlink_policy,
+        follow_symlinks: opt.follow_symlinks,
+        one_file_system: opt.one_file_system,
+        skip_fs_types: skipped_fs_types(&opt.skip_fs_type, &opt.include_fs_type),
+        filter,
+        measure_excluded: opt.show_excluded,
+        count_special: opt.count_special,
+        // only the symlink report and the json output list the links
+        inspect_symlinks: opt.symlinks || opt.format == OutputFormat::Json,
+        // du needs the listing order, the table the metadata of the nodes
+        record_details: opt.format == OutputFormat::Du
+            || opt.columns.iter().any(|column| matches!(column, Column::Mtime | Column::Owner)),
+    };
+    let options = if opt.format == OutputFormat::Du {
+        // du counts every entry once, where it first comes across it
+        ScanOptions { count_special: true, hardlink_policy: HardlinkPolicy::Traversal, ..options }
     } else {
-        process_dir(root_path_str)
+        options
     };
+    if opt.format == OutputFormat::Ndjson {
+        return stream_ndjson(canonicalized_path, opt.threads, &options, opt.escape, start);
+    }
+
+    let scan = if opt.threads > 1 {
+        process_dir_threaded(canonicalized_path, opt.threads, &options)
+    } else {
+        process_dir(canonicalized_path, &options)
+    }
+    .map_err(|error| error.to_string())?;
+
+    match opt.format {
+        OutputFormat::Json => {
+            let report = JsonReport::new(&scan, &options, start.elapsed(), opt.escape);
+            println!("{}", serde_json::to_string(&report).map_err(|error| error.to_string())?);
+            return Result::Ok(());
+        }
+        OutputFormat::Csv | OutputFormat::Tsv => {
+            let delimiter = if opt.format == OutputFormat::Csv { b',' } else { b'\t' };
+            return write_table(io::stdout().lock(), &scan, &opt.columns, delimiter, opt.escape);
+        }
+        OutputFormat::Du => {
+            return print_du(&scan, &opt.path, &du_options, opt.escape);
+        }
+        OutputFormat::Text | OutputFormat::Ndjson => {}
+    }
 
     // by default list the largest files first  
-    let mut entries: Vec<(&_, &_)> = scan.result.iter().collect();
+    let mut entries: Vec<(NodeIndex, &TreeNode)> = scan.tree.iter().collect();
     entries.sort_by(|a, b| {
         if a.1.node_type != b.1.node_type {
             if a.1.node_type == NodeType::File {
@@ -64,23 +275,55 @@ fn main() -> Result<(), String> {
     // benchmarking -- for me the optimal number of threads to use is 8
     println!("Runtime: {duration:.2?}", duration=start.elapsed());
 
-    let mut count = 0;
-    for entry in entries {
-        if entry.1.node_type == NodeType::File {
-            count += 1;
-            if count > opt.top {
-                break;
+    if let Some(ranking) = opt.directories {
+        print_top_directories(&scan, ranking, opt.top, opt.escape);
+    } else {
+        let mut count = 0;
+        for entry in entries {
+            if entry.1.node_type == NodeType::File {
+                count += 1;
+                if count > opt.top {
+                    break;
+                }
+                println!("{path}{dir} {size}", path = display_path(&scan.tree.path(entry.0), opt.escape), size = display_size(entry.1.size, entry.1.apparent_size), dir = if entry.1.node_type == NodeType::Directory { MAIN_SEPARATOR.into() } else { String::new() });
             }
-            println!("{path}{dir} {size}", path = entry.0, size = to_decimal_prefix(entry.1.size as i128), dir = if entry.1.node_type == NodeType::Directory { MAIN_SEPARATOR.into() } else { String::new() });
         }
     }
 
+    if let Some(max_depth) = opt.max_depth {
+        print_directories(&scan, max_depth, opt.escape);
+    }
+
     if opt.hardlinks {
-        let double_count: u128 = scan.double_count.iter().fold(0, |acc, entry| {
-            acc + entry.1.size
-        });
-        println!("\nFiles referenced multiple times (via hardlink): {}", to_decimal_prefix(double_count as i128));
+        print_hardlinks(&scan, opt.top, opt.escape);
+    }
+
+    if opt.devices {
+        print_devices(&scan, opt.escape);
+    }
+
+    print_skipped_mounts(&scan, opt.escape);
+    print_directory_aliases(&scan, opt.top, opt.escape);
+
+    if opt.show_excluded {
+        print_excluded(&scan);
+    }
+
+    if opt.symlinks {
+        print_sym_links(&scan, opt.top, opt.escape);
+    }
+
+    if opt.special_files {
+        print_special_files(&scan, opt.top, opt.escape);
+    }
+
+    if !scan.errors.is_empty() {
+        println!(
+            "\n{count} directories unreadable, their contents are not counted ({errors} errors)",
+            count = scan.unreadable_directories(),
+            errors = scan.errors.len()
+        );
     }
 
-    return Result::Ok(());
+    Result::Ok(())
 }
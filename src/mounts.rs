@@ -0,0 +1,293 @@
+use std::path::{Path, PathBuf};
+
+// file system types skipped by default, kernel pseudo file systems whose
+// sizes are meaningless and network file systems that can hang a scan, which
+// includes every FUSE file system (`fuse` matches `fuse.sshfs`, `fuse.rclone`
+// and so on, FUSE drivers of local disks are typed `fuseblk`)
+pub const DEFAULT_SKIPPED_FS_TYPES: [&str; 25] = [
+    "autofs",
+    "binfmt_misc",
+    "bpf",
+    "cgroup",
+    "cgroup2",
+    "configfs",
+    "debugfs",
+    "devpts",
+    "devtmpfs",
+    "efivarfs",
+    "fusectl",
+    "hugetlbfs",
+    "mqueue",
+    "proc",
+    "pstore",
+    "securityfs",
+    "sysfs",
+    "tracefs",
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "smbfs",
+    "afs",
+    "fuse",
+];
+
+#[derive(Debug, Clone)]
+pub struct MountInfo {
+    pub mount_point: PathBuf,
+    pub fs_type: String,
+
+    // `st_dev` of the nodes on this mount
+    pub device: u64,
+}
+
+// whether `fs_type` is matched by an entry of a type list, an entry also
+// matches every subtype of it (`fuse` matches `fuse.sshfs`)
+pub fn matches_fs_type(fs_type: &str, types: &[String]) -> bool {
+    types.iter().any(|entry| {
+        fs_type == entry
+            || fs_type
+                .strip_prefix(entry.as_str())
+                .is_some_and(|subtype| subtype.starts_with('.'))
+    })
+}
+
+// the mount table of the running system, later (over)mounts come last
+#[cfg(target_os = "linux")]
+pub fn read_mounts() -> Vec<MountInfo> {
+    std::fs::read_to_string("/proc/self/mountinfo")
+        .map(|table| parse_mountinfo(&table))
+        .unwrap_or_default()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn read_mounts() -> Vec<MountInfo> {
+    Vec::new()
+}
+
+// the mount a path lives on, given a mount table from `read_mounts`
+pub fn mount_of<'a>(mounts: &'a [MountInfo], path: &Path) -> Option<&'a MountInfo> {
+    mounts
+        .iter()
+        .rev()
+        .filter(|mount| path.starts_with(&mount.mount_point))
+        .max_by_key(|mount| mount.mount_point.components().count())
+}
+
+// the mount holding the nodes of `device` that were found at `path`, falls
+// back to any mount of the device for bind mounts and overmounted paths
+pub fn mount_of_device<'a>(mounts: &'a [MountInfo], device: u64, path: &Path) -> Option<&'a MountInfo> {
+    mount_of(mounts, path)
+        .filter(|mount| mount.device == device)
+        .or_else(|| mounts.iter().rev().find(|mount| mount.device == device))
+}
+
+// major and minor number of a device, the inverse of `make_device`
+pub fn device_numbers(device: u64) -> (u64, u64) {
+    let major = ((device >> 32) & 0xffff_f000) | ((device >> 8) & 0xfff);
+    let minor = ((device >> 12) & 0xffff_ff00) | (device & 0xff);
+    (major, minor)
+}
+
+// parse the lines of /proc/self/mountinfo:
+// `id parent major:minor root mount-point options [optional...] - type source super-options`
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_mountinfo(table: &str) -> Vec<MountInfo> {
+    table
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let device = fields.nth(2)?;
+            let mount_point = fields.nth(1)?;
+            let fs_type = fields.skip_while(|&field| field != "-").nth(1)?;
+            let (major, minor) = device.split_once(':')?;
+            Some(MountInfo {
+                mount_point: unescape(mount_point),
+                fs_type: fs_type.to_string(),
+                device: make_device(major.parse().ok()?, minor.parse().ok()?),
+            })
+        })
+        .collect()
+}
+
+// the kernel encodes space, tab, newline and backslash in paths as `\ooo`
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn unescape(field: &str) -> PathBuf {
+    let bytes = field.as_bytes();
+    let mut unescaped: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let octal = bytes.get(index + 1..index + 4).filter(|_| bytes[index] == b'\\');
+        match octal.and_then(|octal| u8::from_str_radix(std::str::from_utf8(octal).ok()?, 8).ok()) {
+            Some(byte) => {
+                unescaped.push(byte);
+                index += 4;
+            }
+            None => {
+                unescaped.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    path_from_bytes(unescaped)
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(std::ffi::OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// glibc's `makedev`, the encoding of `st_dev` on linux
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn make_device(major: u64, minor: u64) -> u64 {
+    ((major & 0xffff_f000) << 32) | ((major & 0xfff) << 8) | ((minor & 0xffff_ff00) << 12) | (minor & 0xff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::{sample, Fixture};
+    use crate::types::{FollowSymlinks, HardlinkPolicy, NodeType, ScanOptions, SizeMode};
+    use crate::walk::SequentialWalker;
+    use crate::walk_async::ThreadedWalker;
+    use crate::walker::{scan, scan_context, ScanContext};
+
+    const TABLE: &str = "\
+23 28 0:22 / /proc rw,relatime - proc proc rw
+28 1 254:0 / / rw,relatime - ext4 /dev/vda rw,discard
+40 28 0:45 / /mnt/my\\040share rw,relatime shared:12 - nfs4 server:/export rw
+41 28 0:46 / /home/user/remote rw - fuse.sshfs user@host: rw";
+
+    #[test]
+    fn test_parse_mountinfo() {
+        let mounts = parse_mountinfo(TABLE);
+        assert_eq!(mounts.len(), 4);
+        assert_eq!(mounts[0].mount_point, PathBuf::from("/proc"));
+        assert_eq!(mounts[0].fs_type, "proc");
+        assert_eq!(mounts[1].device, 65024);
+        assert_eq!(mounts[2].mount_point, PathBuf::from("/mnt/my share"));
+        assert_eq!(mounts[2].fs_type, "nfs4");
+        assert_eq!(mounts[3].fs_type, "fuse.sshfs");
+    }
+
+    #[test]
+    fn test_mount_of() {
+        let mounts = parse_mountinfo(TABLE);
+        assert_eq!(mount_of(&mounts, Path::new("/proc/1/status")).unwrap().fs_type, "proc");
+        assert_eq!(mount_of(&mounts, Path::new("/home/user")).unwrap().fs_type, "ext4");
+        let root = mount_of_device(&mounts, 65024, Path::new("/home/user/remote/bind")).unwrap();
+        assert_eq!(root.mount_point, PathBuf::from("/"));
+        assert_eq!(device_numbers(make_device(259, 1048577)), (259, 1048577));
+    }
+
+    #[test]
+    fn test_matches_fs_type() {
+        let types = vec![String::from("fuse"), String::from("nfs")];
+        assert!(matches_fs_type("fuse.sshfs", &types));
+        assert!(matches_fs_type("nfs", &types));
+        assert!(!matches_fs_type("nfs4", &types));
+        assert!(!matches_fs_type("fuseblk", &types));
+        let defaults: Vec<String> = DEFAULT_SKIPPED_FS_TYPES.iter().map(|fs_type| fs_type.to_string()).collect();
+        assert!(matches_fs_type("fuse.rclone", &defaults));
+        assert!(!matches_fs_type("ext4", &defaults));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_skip_mount_behind_symlink() {
+        let outside = Fixture::new("mounted");
+        outside.file("remote/file", 10000);
+        let fixture = Fixture::new("mount-link");
+        fixture.symlink(outside.path("remote"), "link");
+        let options = ScanOptions { follow_symlinks: FollowSymlinks::Always, ..ScanOptions::default() };
+        let context = ScanContext::new(fixture.root.clone(), &options).unwrap();
+        let followed = scan_context(&SequentialWalker, context).unwrap();
+        assert!(followed.tree.find(&fixture.path("link/file")).is_some());
+
+        // the target of the link pretends to be on a network file system,
+        // which only shows once the link is resolved
+        let mut context = ScanContext::new(fixture.root.clone(), &options).unwrap();
+        context.skipped_devices.insert(context.root_device, String::from("nfs"));
+        let skipped = scan_context(&SequentialWalker, context).unwrap();
+        let mounts: Vec<(PathBuf, Option<&str>)> = skipped
+            .skipped_mounts
+            .iter()
+            .map(|mount| (mount.path.clone(), mount.fs_type.as_deref()))
+            .collect();
+        assert_eq!(mounts, vec![(fixture.path("link"), Some("nfs"))]);
+        assert_eq!(skipped.tree.len(), 1);
+    }
+
+    #[test]
+    fn test_skipped_mounts() {
+        let fixture = sample();
+        let options = ScanOptions { one_file_system: true, ..ScanOptions::default() };
+        let same_device = scan(&SequentialWalker, fixture.root.clone(), &options).unwrap();
+        assert!(same_device.skipped_mounts.is_empty());
+        assert!(same_device.tree.find(&fixture.path("a/b/c/three")).is_some());
+
+        for threads in [1, 4] {
+            // the root pretends to be on another device than everything
+            // below it and `d` is a mount point of a skipped type
+            let mut context = ScanContext::new(fixture.root.clone(), &options).unwrap();
+            context.root_device = !context.root_device;
+            context.mounts = vec![MountInfo { mount_point: fixture.path("a"), fs_type: String::from("stubfs"), device: 1 }];
+            context.skipped_mount_points.insert(fixture.path("d"), String::from("nfs"));
+            let scan = scan_context(&ThreadedWalker { threads }, context).unwrap();
+
+            let skipped: Vec<(PathBuf, Option<&str>)> = scan
+                .skipped_mounts
+                .iter()
+                .map(|mount| (mount.path.clone(), mount.fs_type.as_deref()))
+                .collect();
+            assert_eq!(skipped, vec![
+                (fixture.path("a"), Some("stubfs")),
+                (fixture.path("d"), Some("nfs")),
+                (fixture.path("empty"), None),
+            ]);
+            for skipped in ["a", "a/one", "d", "empty"] {
+                assert_eq!(scan.tree.find(&fixture.path(skipped)), None, "{}", skipped);
+            }
+            assert!(scan.tree.find(&fixture.path("top")).is_some());
+        }
+    }
+
+    #[test]
+    fn test_device_usage() {
+        let fixture = sample();
+        let options = ScanOptions {
+            size_mode: SizeMode::Both,
+            hardlink_policy: HardlinkPolicy::Split,
+            ..ScanOptions::default()
+        };
+        for threads in [1, 4] {
+            // the scanned device is only mounted elsewhere and bound into
+            // `/`, which is the mount point of another device
+            let mut context = ScanContext::new(fixture.root.clone(), &options).unwrap();
+            let device = context.root_device;
+            context.mounts = vec![
+                MountInfo { mount_point: PathBuf::from("/srv/data"), fs_type: String::from("ext4"), device },
+                MountInfo { mount_point: PathBuf::from("/"), fs_type: String::from("overlay"), device: !device },
+            ];
+            let scan = scan_context(&ThreadedWalker { threads }, context).unwrap();
+            assert_eq!(scan.devices.len(), 1);
+            assert_eq!(scan.devices[0].mount_point, Some(PathBuf::from("/srv/data")));
+            assert_eq!(scan.devices[0].fs_type.as_deref(), Some("ext4"));
+
+            // the shares of the devices add up to the totals of the root
+            let root = scan.tree.get(scan.tree.root());
+            assert_eq!(scan.devices.iter().map(|usage| usage.size).sum::<u64>(), root.size);
+            let apparent_sizes: Option<u64> = scan.devices.iter().map(|usage| usage.apparent_size).sum();
+            assert_eq!(apparent_sizes, root.apparent_size);
+            let files = scan.tree.iter().filter(|(_, node)| node.node_type == NodeType::File).count();
+            assert_eq!(scan.devices.iter().map(|usage| usage.files).sum::<usize>(), files);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::fs::Metadata;
+use std::path::Path;
+
+use std::time::UNIX_EPOCH;
+
+use crate::types::{NodeAttributes, NodeId};
+
+#[cfg(unix)]
+mod implementation {
+    use super::*;
+
+    use std::os::unix::fs::MetadataExt;
+
+    pub const IDENTIFIES_FILES: bool = true;
+
+    pub fn node_id(_path: &Path, metadata: &Metadata) -> NodeId {
+        (metadata.dev(), metadata.ino())
+    }
+
+    pub fn link_count(metadata: &Metadata) -> u64 {
+        metadata.nlink()
+    }
+
+    pub fn owner(metadata: &Metadata) -> Option<u32> {
+        Some(metadata.uid())
+    }
+}
+
+#[cfg(not(unix))]
+mod implementation {
+    use super::*;
+
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    pub const IDENTIFIES_FILES: bool = false;
+
+    // there is no stable device/file index on these platforms so the path is
+    // used as the identity, which disables hardlink detection but keeps
+    // every node distinct
+    pub fn node_id(path: &Path, _metadata: &Metadata) -> NodeId {
+        let mut hasher = DefaultHasher::new();
+        path.hash(&mut hasher);
+        (0, hasher.finish())
+    }
+
+    pub fn link_count(_metadata: &Metadata) -> u64 {
+        1
+    }
+
+    pub fn owner(_metadata: &Metadata) -> Option<u32> {
+        None
+    }
+}
+
+// whether a node id identifies a file rather than the path it was reached
+// at, which following every symlink relies on to count a target reached
+// through several links once and to break cycles of links
+pub const IDENTIFIES_FILES: bool = self::implementation::IDENTIFIES_FILES;
+
+pub fn node_id(path: &Path, metadata: &Metadata) -> NodeId {
+    self::implementation::node_id(path, metadata)
+}
+
+// number of hardlinks to a node anywhere on its file system
+pub fn link_count(metadata: &Metadata) -> u64 {
+    self::implementation::link_count(metadata)
+}
+
+// modification time and owner of a node
+pub fn attributes(metadata: &Metadata) -> NodeAttributes {
+    let modified = metadata.modified().ok().map(|modified| match modified.duration_since(UNIX_EPOCH) {
+        Ok(after) => after.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    });
+    NodeAttributes {
+        modified,
+        owner: self::implementation::owner(metadata),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::test_support::Fixture;
+
+    #[test]
+    fn test_node_id() {
+        let fixture = Fixture::new("node-id");
+        fixture.file("one", 10).file("two", 10).hard_link("one", "one-link");
+        let id = |relative: &str| {
+            let path = fixture.path(relative);
+            node_id(&path, &fs::symlink_metadata(&path).unwrap())
+        };
+        assert_eq!(id("one"), id("one-link"));
+        assert_ne!(id("one"), id("two"));
+        assert_eq!(link_count(&fs::symlink_metadata(fixture.path("one")).unwrap()), 2);
+        // which every symlink can be followed with
+        assert_eq!("always".parse(), Ok(crate::types::FollowSymlinks::Always));
+    }
+}
@@ -1,40 +1,391 @@
-use std::os::macos::fs::MetadataExt;
 use std::fs;
+use std::path::{Path, PathBuf};
 
-use crate::types::{DirectoryInfo, DirectoryResult, FileInfo, NodeInfo, SymLinkInfo};
-use crate::file_size::file_size;
+use crate::types::{DirectoryInfo, DirectoryResult, Excluded, FileInfo, FollowSymlinks, NodeInfo, ScanError, ScanOperation, SpecialEntry, SpecialFiles, SpecialKind, SymLinkInfo, SymLinkStatus};
+use crate::file_size::sizes;
+use crate::node_id::{attributes, link_count, node_id, IDENTIFIES_FILES};
+use crate::symlink::inspect;
+use crate::walker::ScanContext;
 
-pub fn process_directory(directory_path: &String) -> std::io::Result<DirectoryResult> {
+pub fn process_directory(directory_path: &Path, context: &ScanContext) -> Result<DirectoryResult, ScanError> {
+    let options = &context.options;
     let mut directories: Vec<DirectoryInfo> = Vec::new();
     let mut files: Vec<FileInfo> = Vec::new();
     let mut sym_links: Vec<SymLinkInfo> = Vec::new();
-    for file in fs::read_dir(directory_path)? {
-        let path = file.unwrap().path();
-        let path_str = path.to_str().unwrap();
-        let sym_meta = fs::symlink_metadata(path_str).unwrap();
-
-        // if the node is a symlink then ignore it
-        if sym_meta.file_type().is_symlink() {
-            sym_links.push(SymLinkInfo {
-                path: String::from(path_str)
-            });
+    let mut errors: Vec<ScanError> = Vec::new();
+    let mut excluded = Excluded::default();
+    let mut special_files = SpecialFiles::default();
+    let mut counted_special: Vec<SpecialEntry> = Vec::new();
+    let mut count_special = |path: PathBuf, position: usize, metadata: &fs::Metadata| {
+        if options.count_special {
+            let (size, apparent_size) = sizes(metadata, options.size_mode);
+            let id = node_id(&path, metadata);
+            counted_special.push(SpecialEntry { path, id, position, size, apparent_size });
+        }
+    };
+
+    let entries = match fs::read_dir(directory_path) {
+        Ok(entries) => entries,
+        // the blocks of the directory itself were counted with the listing
+        // of its parent (or the context for the root), only its contents are
+        // unknown
+        Err(e) => return Err(ScanError::new(directory_path.to_path_buf(), ScanOperation::ReadDir, &e)),
+    };
+    for (position, file) in entries.enumerate() {
+        let entry = match file {
+            Ok(entry) => entry,
+            Err(e) => {
+                errors.push(ScanError::new(directory_path.to_path_buf(), ScanOperation::ReadEntry, &e));
+                continue;
+            }
+        };
+        let path = entry.path();
+        if context.skip_mount_point(&path) {
+            continue;
+        }
+        if !options.filter.is_empty() {
+            // the type of an entry usually comes with the listing, no stat needed
+            let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+            let relative_path = path.strip_prefix(&context.root_path).unwrap_or(&path);
+            if options.filter.excludes(relative_path, is_dir) {
+                excluded.entries += 1;
+                if options.measure_excluded {
+                    let (size, apparent_size) = measure(&path, context);
+                    excluded.add(Excluded { entries: 0, size: Some(size), apparent_size });
+                }
+                continue;
+            }
+        }
+        let sym_meta = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                errors.push(ScanError::new(path, ScanOperation::Metadata, &e));
+                continue;
+            }
+        };
+
+        // symlinks are only followed if their target lies outside of the scan
+        // root (targets inside of it are counted where they are), otherwise
+        // only the blocks of the link itself may be counted, links are only
+        // resolved when they may be followed or are reported
+        let is_symlink = sym_meta.file_type().is_symlink();
+        let sym_meta = if is_symlink {
+            let follow = options.follow_symlinks == FollowSymlinks::Always && IDENTIFIES_FILES;
+            let mut followed = None;
+            if follow || options.inspect_symlinks {
+                let link = inspect(path.clone(), &context.root_path);
+                if follow && link.status == SymLinkStatus::External {
+                    followed = fs::metadata(&path).ok();
+                }
+                if options.inspect_symlinks {
+                    sym_links.push(link);
+                }
+            }
+            match followed {
+                Some(metadata) => metadata,
+                None => {
+                    count_special(path, position, &sym_meta);
+                    continue;
+                }
+            }
+        } else {
+            sym_meta
+        };
+        // the metadata is not a symlink's so it is already the target's metadata
+        let id = node_id(&path, &sym_meta);
+        if context.skip_device(&path, id.0) {
             continue;
         }
-        let metadata = fs::metadata(path_str).unwrap();
         let node = NodeInfo {
-            id: (metadata.st_dev(), metadata.st_ino()),
-            path: String::from(path_str),
+            id,
+            position,
+            attributes: attributes(&sym_meta),
+            path,
         };
 
         // if the node is a file then add a NodeInfo under its path to the result with file size
         if sym_meta.is_file() {
+            let (size, apparent_size) = sizes(&sym_meta, options.size_mode);
+            // a followed file may be the target of other links as well, it is
+            // counted once like a file with several hardlinks
+            let links = link_count(&sym_meta);
             files.push(FileInfo {
-                size: file_size(&sym_meta),
+                size,
+                apparent_size,
+                links: if is_symlink { links.max(2) } else { links },
                 node,
             });
         } else if sym_meta.is_dir() {
-            directories.push(DirectoryInfo { node });
+            let (size, apparent_size) = sizes(&sym_meta, options.size_mode);
+            directories.push(DirectoryInfo {
+                size,
+                apparent_size,
+                node,
+            });
+        } else {
+            special_files.add(special_kind(&sym_meta.file_type()), sizes(&sym_meta, options.size_mode).0);
+            count_special(node.path, position, &sym_meta);
+        }
+    }
+    Ok(DirectoryResult {
+        files,
+        directories,
+        sym_links,
+        errors,
+        excluded,
+        counted_special,
+        special_files,
+    })
+}
+
+#[cfg(unix)]
+fn special_kind(file_type: &fs::FileType) -> SpecialKind {
+    use std::os::unix::fs::FileTypeExt;
+    if file_type.is_socket() {
+        SpecialKind::Socket
+    } else if file_type.is_fifo() {
+        SpecialKind::Fifo
+    } else if file_type.is_block_device() {
+        SpecialKind::BlockDevice
+    } else if file_type.is_char_device() {
+        SpecialKind::CharDevice
+    } else {
+        SpecialKind::Other
+    }
+}
+
+#[cfg(not(unix))]
+fn special_kind(_file_type: &fs::FileType) -> SpecialKind {
+    SpecialKind::Other
+}
+
+// size of an excluded entry and everything below it on the same device
+// (the one of the root with `-x`), symlinks are not followed, skipped mount
+// points are left out and unreadable entries count as empty, every directory
+// and hardlinked file is counted once
+fn measure(path: &Path, context: &ScanContext) -> (u64, Option<u64>) {
+    let options = &context.options;
+    let mut size = 0;
+    let mut apparent_size = None;
+    let mut device = None;
+    let mut pending: Vec<PathBuf> = vec![path.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        if context.skip_mount_point(&path) {
+            continue;
+        }
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        let id = node_id(&path, &metadata);
+        if context.skip_device(&path, id.0) {
+            continue;
+        }
+        let device = *device.get_or_insert(if options.one_file_system { context.root_device } else { id.0 });
+        if id.0 != device {
+            continue;
+        }
+        let first = if metadata.is_dir() {
+            context.first_visit(id)
+        } else {
+            link_count(&metadata) < 2 || context.first_measured(id)
+        };
+        if !first {
+            continue;
+        }
+        let (entry_size, entry_apparent_size) = sizes(&metadata, options.size_mode);
+        size += entry_size;
+        if let Some(entry_apparent_size) = entry_apparent_size {
+            *apparent_size.get_or_insert(0) += entry_apparent_size;
+        }
+        if metadata.is_dir() {
+            if let Ok(entries) = fs::read_dir(&path) {
+                pending.extend(entries.flatten().map(|entry| entry.path()));
+            }
         }
     }
-    Ok(DirectoryResult { files, directories, sym_links })
-}
\ No newline at end of file
+    (size, apparent_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::filter::PathFilter;
+    use crate::test_support::{sample, Fixture};
+    use crate::types::{ScanOptions, SizeMode};
+    use crate::walk::SequentialWalker;
+    use crate::walk_async::ThreadedWalker;
+    use crate::walker::scan;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_symlinks() {
+        let outside = Fixture::new("outside");
+        outside.file("data/blob", 20000).symlink(outside.path("data"), "data/loop").file("single", 100000);
+        let fixture = Fixture::new("symlinks");
+        fixture
+            .file("real/file", 3000)
+            .symlink(outside.path("data"), "external")
+            .symlink(outside.path("data"), "external-again")
+            .symlink(outside.path("single"), "external-file")
+            .symlink(outside.path("single"), "external-file-again")
+            .symlink(fixture.path("real"), "internal")
+            .symlink(fixture.path("real/file"), "internal-file")
+            .symlink("missing", "broken")
+            .symlink(fixture.path("."), "real/up");
+
+        let total = |follow_symlinks: FollowSymlinks, threads: u8| {
+            let options = ScanOptions { follow_symlinks, ..ScanOptions::default() };
+            let scan = scan(&ThreadedWalker { threads }, fixture.root.clone(), &options).unwrap();
+            scan.tree.get(scan.tree.root()).size
+        };
+        let blob = std::fs::symlink_metadata(outside.path("data/blob")).unwrap();
+        let data = std::fs::symlink_metadata(outside.path("data")).unwrap();
+        let data_size = crate::file_size::file_size(&blob) + crate::file_size::file_size(&data);
+        let single = crate::file_size::file_size(&std::fs::symlink_metadata(outside.path("single")).unwrap());
+
+        let not_followed = total(FollowSymlinks::Root, 1);
+        // the external directory and file are counted once no matter how
+        // often they are linked, links into the scan and loops add nothing
+        for threads in [1, 4] {
+            assert_eq!(total(FollowSymlinks::Always, threads), not_followed + data_size + single);
+        }
+
+        // revisits are reported at the same paths whichever path was listed
+        for threads in [1, 4] {
+            let options = ScanOptions { follow_symlinks: FollowSymlinks::Always, ..ScanOptions::default() };
+            let scan = scan(&ThreadedWalker { threads }, fixture.root.clone(), &options).unwrap();
+            // the external directory is counted below the first link to it,
+            // the link into the root adds nothing to the directory it is in
+            let size_of = |relative: &str| scan.tree.find(&fixture.path(relative)).map(|index| scan.tree.get(index).size);
+            assert_eq!(size_of("external"), Some(data_size));
+            assert_eq!(size_of("external-again"), Some(0));
+            assert_eq!(size_of("external-file"), Some(single));
+            assert_eq!(size_of("external-file-again"), None);
+            assert_eq!(scan.hardlinks[0].paths, vec![fixture.path("external-file"), fixture.path("external-file-again")]);
+            assert_eq!(size_of("internal"), None);
+            let real = std::fs::symlink_metadata(fixture.path("real")).unwrap();
+            let file = std::fs::symlink_metadata(fixture.path("real/file")).unwrap();
+            let real_size = crate::file_size::file_size(&real) + crate::file_size::file_size(&file);
+            assert_eq!(size_of("real"), Some(real_size));
+            let aliases: Vec<(&Path, &Path, bool)> = scan
+                .directory_aliases
+                .iter()
+                .map(|alias| (alias.path.as_path(), alias.target.as_path(), alias.is_loop))
+                .collect();
+            // `external/loop` is a symlink to its own ancestor and not followed
+            let (again, external) = (fixture.path("external-again"), fixture.path("external"));
+            assert_eq!(aliases, vec![(again.as_path(), external.as_path(), false)]);
+            assert!(scan.tree.find(&fixture.path("external/blob")).is_some());
+        }
+    }
+
+    #[test]
+    fn test_excluded_subtrees() {
+        let fixture = sample();
+        let excludes = vec![String::from("b"), String::from("d/*")];
+        let options = ScanOptions {
+            filter: PathFilter::new(&excludes, &[]).unwrap(),
+            measure_excluded: true,
+            ..ScanOptions::default()
+        };
+        let full = scan(&SequentialWalker, fixture.root.clone(), &ScanOptions::default()).unwrap();
+        for threads in [1, 4] {
+            let pruned = scan(&ThreadedWalker { threads }, fixture.root.clone(), &options).unwrap();
+            assert_eq!(pruned.tree.find(&fixture.path("a/b")), None);
+            assert_eq!(pruned.tree.find(&fixture.path("d/e")), None);
+            assert!(pruned.tree.find(&fixture.path("d")).is_some());
+            assert_eq!(pruned.excluded.entries, 4);
+            assert!(pruned.excluded.size.unwrap() > 0);
+            assert!(pruned.tree.get(pruned.tree.root()).size < full.tree.get(full.tree.root()).size);
+        }
+    }
+
+    #[test]
+    fn test_measure_excluded() {
+        let fixture = Fixture::new("measure");
+        fixture
+            .file("x/big", 50000)
+            .hard_link("x/big", "x/big-link")
+            .hard_link("x/big", "big-link")
+            .file("x/y/small", 10)
+            .file("kept", 1);
+        let excludes = vec![String::from("x"), String::from("big-link")];
+        let options = ScanOptions {
+            filter: PathFilter::new(&excludes, &[]).unwrap(),
+            measure_excluded: true,
+            size_mode: SizeMode::Both,
+            ..ScanOptions::default()
+        };
+        // the hardlinked file is measured once over both excluded entries
+        let (mut size, mut apparent_size) = (0, 0);
+        for relative in ["x", "x/big", "x/y", "x/y/small"] {
+            let metadata = std::fs::symlink_metadata(fixture.path(relative)).unwrap();
+            let sizes = sizes(&metadata, options.size_mode);
+            size += sizes.0;
+            apparent_size += sizes.1.unwrap();
+        }
+        for threads in [1, 4] {
+            let scan = scan(&ThreadedWalker { threads }, fixture.root.clone(), &options).unwrap();
+            assert_eq!(scan.excluded, Excluded { entries: 2, size: Some(size), apparent_size: Some(apparent_size) });
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_directory_blocks() {
+        // enough long names that the directory needs more than one block
+        let fixture = Fixture::new("blocks");
+        for entry in 0..300 {
+            fixture.file(&format!("many/{:0>40}", entry), 0);
+        }
+        fixture.dir("empty/nested").symlink("x".repeat(200), "long-link");
+        let size_of = |relative: &str| {
+            crate::file_size::file_size(&std::fs::symlink_metadata(fixture.path(relative)).unwrap())
+        };
+        let directories: u64 = ["", "many", "empty", "empty/nested"].into_iter().map(size_of).sum();
+
+        for count_special in [false, true] {
+            let options = ScanOptions { count_special, ..ScanOptions::default() };
+            let scan = scan(&ThreadedWalker { threads: 4 }, fixture.root.clone(), &options).unwrap();
+            // the files are empty, everything counted is the blocks of the
+            // directories and, when counted, of the symlink
+            let link = if count_special { size_of("long-link") } else { 0 };
+            assert_eq!(scan.tree.get(scan.tree.root()).size, directories + link);
+            assert_eq!(scan.tree.get(scan.tree.root()).exclusive_size, size_of("") + link);
+            let many = scan.tree.find(&fixture.path("many")).unwrap();
+            assert_eq!(scan.tree.get(many).size, size_of("many"));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_special_files() {
+        let fixture = Fixture::new("special");
+        fixture.dir("run/nested");
+        let _first = std::os::unix::net::UnixListener::bind(fixture.path("run/first.sock")).unwrap();
+        let _second = std::os::unix::net::UnixListener::bind(fixture.path("run/nested/second.sock")).unwrap();
+
+        let listed = scan(&SequentialWalker, fixture.root.clone(), &ScanOptions::default()).unwrap();
+        let directories: Vec<&PathBuf> = listed.special_files.iter().map(|(path, _)| path).collect();
+        assert_eq!(directories, vec![&fixture.path("run"), &fixture.path("run/nested")]);
+        assert_eq!(listed.special_files[0].1.sockets, 1);
+        assert_eq!(listed.special_files[0].1.count(), 1);
+        // special files are not nodes of the tree
+        assert_eq!(listed.tree.find(&fixture.path("run/first.sock")), None);
+        assert!(listed.counted_special.is_empty());
+
+        let options = ScanOptions { count_special: true, ..ScanOptions::default() };
+        let counted = scan(&SequentialWalker, fixture.root.clone(), &options).unwrap();
+        let run = counted.tree.find(&fixture.path("run")).unwrap();
+        let (directory, entry) = counted
+            .counted_special
+            .iter()
+            .find(|(_, entry)| entry.path == fixture.path("run/first.sock"))
+            .unwrap();
+        assert_eq!(*directory, run);
+        assert!(entry.position < 2);
+        assert_eq!(counted.counted_special.len(), 2);
+    }
+}
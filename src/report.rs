@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+
+use dir_stat::mounts::device_numbers;
+use dir_stat::scan_tree::{ScanTree, TreeNode};
+use dir_stat::types::{DirectoryRanking, NodeType, ScanResult, SpecialFiles, SymLinkInfo, SymLinkStatus};
+
+use crate::utils::{display_path, display_size, to_decimal_prefix};
+
+// every group of paths linking to the same inode, largest first
+pub fn print_hardlinks(scan: &ScanResult, top: u8, escape: bool) {
+    let double_count: u64 = scan.double_count.iter().fold(0, |acc, entry| {
+        acc + entry.1.size
+    });
+    println!("\nFiles referenced multiple times (via hardlink): {}", to_decimal_prefix(double_count as i128));
+
+    for group in scan.hardlinks.iter().take(usize::from(top)) {
+        println!(
+            "\n{size} x {links} links (inode {device}:{inode})",
+            size = to_decimal_prefix(group.size as i128),
+            links = group.links(),
+            device = group.id.0,
+            inode = group.id.1
+        );
+        for path in group.paths.iter() {
+            let marker = if path == group.credited() { "*" } else { " " };
+            println!("  {} {}", marker, display_path(path, escape));
+        }
+    }
+    if scan.hardlinks.len() > usize::from(top) {
+        println!("\n... {} more hardlinked files", scan.hardlinks.len() - usize::from(top));
+    }
+}
+
+// broken, external and looping symlinks and the directories holding the most links
+pub fn print_sym_links(scan: &ScanResult, top: u8, escape: bool) {
+    let links = &scan.sym_links;
+    let relative = links.iter().filter(|link| link.is_relative()).count();
+    let count = |status: SymLinkStatus| links.iter().filter(|link| link.status == status).count();
+    println!(
+        "\nSymlinks: {total} ({relative} relative, {absolute} absolute)",
+        total = links.len(),
+        relative = relative,
+        absolute = links.len() - relative
+    );
+    println!(
+        "  {broken} broken, {external} outside the root, {loops} loops",
+        broken = count(SymLinkStatus::Broken),
+        external = count(SymLinkStatus::External),
+        loops = count(SymLinkStatus::Loop) + count(SymLinkStatus::Ancestor)
+    );
+
+    let sections = [
+        ("Broken symlinks", vec![SymLinkStatus::Broken]),
+        ("Symlinks outside the root", vec![SymLinkStatus::External]),
+        ("Symlink loops", vec![SymLinkStatus::Loop, SymLinkStatus::Ancestor]),
+    ];
+    for (title, statuses) in sections.iter() {
+        let matching: Vec<&SymLinkInfo> = links.iter().filter(|link| statuses.contains(&link.status)).collect();
+        if matching.is_empty() {
+            continue;
+        }
+        println!("\n{}:", title);
+        for link in matching.iter().take(usize::from(top)) {
+            println!("  {} -> {}", display_path(&link.path, escape), display_path(&link.target, escape));
+        }
+        if matching.len() > usize::from(top) {
+            println!("  ... {} more", matching.len() - usize::from(top));
+        }
+    }
+
+    let mut per_directory: HashMap<&Path, usize> = HashMap::new();
+    for link in links.iter() {
+        if let Some(parent) = link.path.parent() {
+            *per_directory.entry(parent).or_insert(0) += 1;
+        }
+    }
+    let mut directories: Vec<(&Path, usize)> = per_directory.into_iter().collect();
+    directories.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    if !directories.is_empty() {
+        println!("\nDirectories with the most symlinks:");
+        for (directory, links) in directories.iter().take(usize::from(top)) {
+            println!("  {:>6} {}", links, display_path(directory, escape));
+        }
+    }
+}
+
+// directories reached through more than one path, counted once at their target
+pub fn print_directory_aliases(scan: &ScanResult, top: u8, escape: bool) {
+    if scan.directory_aliases.is_empty() {
+        return;
+    }
+    let loops = scan.directory_aliases.iter().filter(|alias| alias.is_loop).count();
+    println!(
+        "\nDirectories reached more than once: {aliases} ({loops} loops), counted once",
+        aliases = scan.directory_aliases.len(),
+        loops = loops
+    );
+    for alias in scan.directory_aliases.iter().take(usize::from(top)) {
+        println!(
+            "  {path} {kind} {target}",
+            path = display_path(&alias.path, escape),
+            kind = if alias.is_loop { "loops back to" } else { "is an alias of" },
+            target = display_path(&alias.target, escape)
+        );
+    }
+    if scan.directory_aliases.len() > usize::from(top) {
+        println!("  ... {} more", scan.directory_aliases.len() - usize::from(top));
+    }
+}
+
+// mount points that were not crossed
+pub fn print_skipped_mounts(scan: &ScanResult, escape: bool) {
+    if scan.skipped_mounts.is_empty() {
+        return;
+    }
+    println!("\nSkipped mount points:");
+    for mount in scan.skipped_mounts.iter() {
+        match &mount.fs_type {
+            Some(fs_type) => println!("  {} ({})", display_path(&mount.path, escape), fs_type),
+            None => println!("  {}", display_path(&mount.path, escape)),
+        }
+    }
+}
+
+// totals split by the device the files live on
+pub fn print_devices(scan: &ScanResult, escape: bool) {
+    let total: u64 = scan.devices.iter().map(|usage| usage.size).sum();
+    println!("\nDevices:");
+    for usage in scan.devices.iter() {
+        let (major, minor) = device_numbers(usage.device);
+        let mount_point = match &usage.mount_point {
+            Some(mount_point) => display_path(mount_point, escape).into_owned(),
+            None => String::from("?"),
+        };
+        let share = if total == 0 { 0.0 } else { usage.size as f64 * 100.0 / total as f64 };
+        println!(
+            "  {major}:{minor} {mount_point} ({fs_type}) {size} in {files} files, {share:.1}%",
+            major = major,
+            minor = minor,
+            mount_point = mount_point,
+            fs_type = usage.fs_type.as_deref().unwrap_or("unknown"),
+            size = to_decimal_prefix(usage.size as i128),
+            files = usage.files,
+            share = share
+        );
+    }
+}
+
+// total of the entries pruned by exclude and include patterns
+pub fn print_excluded(scan: &ScanResult) {
+    println!(
+        "\nExcluded: {size} in {entries} entries",
+        size = display_size(scan.excluded.size.unwrap_or(0), scan.excluded.apparent_size),
+        entries = scan.excluded.entries
+    );
+}
+
+// directories down to `max_depth` below the root with their cumulative sizes,
+// largest first, everything deeper is part of the size of its ancestor
+pub fn print_directories(scan: &ScanResult, max_depth: usize, escape: bool) {
+    println!("\nDirectories (max depth {}):", max_depth);
+    for (path, node) in directories_to_depth(&scan.tree, max_depth) {
+        println!(
+            "  {size} {path}{separator}",
+            size = display_size(node.size, node.apparent_size),
+            path = display_path(&path, escape),
+            separator = MAIN_SEPARATOR
+        );
+    }
+}
+
+// the directories printed by `print_directories` in the order they are printed
+fn directories_to_depth(tree: &ScanTree, max_depth: usize) -> Vec<(PathBuf, &TreeNode)> {
+    let mut directories: Vec<_> = tree
+        .iter()
+        .filter(|(_, node)| node.node_type == NodeType::Directory && node.depth <= max_depth)
+        .map(|(index, node)| (tree.path(index), node))
+        .collect();
+    directories.sort_by(|a, b| b.1.size.cmp(&a.1.size).then_with(|| a.0.cmp(&b.0)));
+    directories
+}
+
+// the largest directories by inclusive or exclusive size, the other size is
+// shown alongside
+pub fn print_top_directories(scan: &ScanResult, ranking: DirectoryRanking, top: u8, escape: bool) {
+    for index in scan.tree.directories_by_size(ranking).into_iter().take(usize::from(top)) {
+        let node = scan.tree.get(index);
+        let (size, other) = match ranking {
+            DirectoryRanking::Inclusive => (
+                display_size(node.size, node.apparent_size),
+                format!("exclusive {}", display_size(node.exclusive_size, node.exclusive_apparent_size)),
+            ),
+            DirectoryRanking::Exclusive => (
+                display_size(node.exclusive_size, node.exclusive_apparent_size),
+                format!("inclusive {}", display_size(node.size, node.apparent_size)),
+            ),
+        };
+        println!(
+            "{path}{separator} {size} [{other}]",
+            path = display_path(&scan.tree.path(index), escape),
+            separator = MAIN_SEPARATOR,
+            size = size,
+            other = other
+        );
+    }
+}
+
+// sockets, fifos and device nodes by kind and the directories holding the most
+pub fn print_special_files(scan: &ScanResult, top: u8, escape: bool) {
+    let mut total = SpecialFiles::default();
+    for (_, special_files) in scan.special_files.iter() {
+        total.append(special_files);
+    }
+    println!(
+        "\nSpecial files: {count} ({sockets} sockets, {fifos} fifos, {block} block devices, {char} character devices, {other} other), {size}",
+        count = total.count(),
+        sockets = total.sockets,
+        fifos = total.fifos,
+        block = total.block_devices,
+        char = total.char_devices,
+        other = total.other,
+        size = to_decimal_prefix(total.size as i128)
+    );
+
+    let mut directories: Vec<&(PathBuf, SpecialFiles)> = scan.special_files.iter().collect();
+    directories.sort_by(|a, b| b.1.count().cmp(&a.1.count()).then_with(|| a.0.cmp(&b.0)));
+    if !directories.is_empty() {
+        println!("\nDirectories with the most special files:");
+        for (directory, special_files) in directories.iter().take(usize::from(top)) {
+            println!("  {:>6} {}", special_files.count(), display_path(directory, escape));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::OsStr;
+
+    #[test]
+    fn test_directories_to_depth() {
+        let mut tree = ScanTree::new(PathBuf::from("/root"));
+        let root = tree.root();
+        let a = tree.add_node(root, OsStr::new("a"), NodeType::Directory, 4, None);
+        let b = tree.add_node(a, OsStr::new("b"), NodeType::Directory, 4, None);
+        let c = tree.add_node(b, OsStr::new("c"), NodeType::Directory, 4, None);
+        tree.add_node(c, OsStr::new("deep"), NodeType::File, 1000, None);
+        tree.add_node(root, OsStr::new("d"), NodeType::Directory, 4, None);
+        tree.add_node(root, OsStr::new("top"), NodeType::File, 100, None);
+        tree.compute_totals();
+
+        let sizes = |max_depth: usize| -> Vec<(PathBuf, u64)> {
+            directories_to_depth(&tree, max_depth).into_iter().map(|(path, node)| (path, node.size)).collect()
+        };
+        // the file below the cut off is part of the sizes of its ancestors
+        assert_eq!(sizes(1), vec![
+            (PathBuf::from("/root"), 1116),
+            (PathBuf::from("/root/a"), 1012),
+            (PathBuf::from("/root/d"), 4),
+        ]);
+        assert_eq!(sizes(0), vec![(PathBuf::from("/root"), 1116)]);
+        assert_eq!(sizes(5).len(), 5);
+    }
+}
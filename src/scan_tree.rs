@@ -0,0 +1,451 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::{DirectoryRanking, DirectoryScanResult, NodeAttributes, NodeId, NodeResult, NodeType};
+
+pub type NodeIndex = usize;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TreeNode {
+    // file name of the node (the full path for the root)
+    pub name: Box<OsStr>,
+    pub parent: Option<NodeIndex>,
+    pub children: Vec<NodeIndex>,
+    pub node_type: NodeType,
+
+    // number of ancestors between the node and the root
+    pub depth: usize,
+
+    // size of the node and everything below it
+    pub size: u64,
+    pub apparent_size: Option<u64>,
+
+    // size of the node itself plus its direct files and other entries (not
+    // subdirectories)
+    pub exclusive_size: u64,
+    pub exclusive_apparent_size: Option<u64>,
+
+    // number of files of the node and everything below it
+    pub files: usize,
+}
+
+// where a node was listed and its metadata, neither affects sizes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct NodeDetails {
+    pub id: NodeId,
+
+    // position of the entry in the listing of its parent (readdir order)
+    pub position: usize,
+
+    pub attributes: NodeAttributes,
+}
+
+// hierarchical scan result stored as an arena of nodes linked by index,
+// a node is always stored after its parent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanTree {
+    nodes: Vec<TreeNode>,
+
+    // details by node index, only kept once `record_details` was called as
+    // few reports need them
+    details: Option<Vec<NodeDetails>>,
+}
+
+fn add_apparent(total: &mut Option<u64>, apparent_size: Option<u64>) {
+    if let Some(apparent_size) = apparent_size {
+        *total.get_or_insert(0) += apparent_size;
+    }
+}
+
+impl ScanTree {
+    pub fn new(root_path: PathBuf) -> ScanTree {
+        ScanTree {
+            nodes: vec![TreeNode {
+                name: root_path.into_os_string().into_boxed_os_str(),
+                parent: None,
+                children: Vec::new(),
+                node_type: NodeType::Directory,
+                depth: 0,
+                size: 0,
+                apparent_size: None,
+                exclusive_size: 0,
+                exclusive_apparent_size: None,
+                files: 0,
+            }],
+            details: None,
+        }
+    }
+
+    // keep the details passed to `describe` from now on
+    pub fn record_details(&mut self) {
+        self.details.get_or_insert_with(|| vec![NodeDetails::default(); self.nodes.len()]);
+    }
+
+    pub fn root(&self) -> NodeIndex {
+        0
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn get(&self, index: NodeIndex) -> &TreeNode {
+        &self.nodes[index]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (NodeIndex, &TreeNode)> {
+        self.nodes.iter().enumerate()
+    }
+
+    // children of a node, largest first once `sort_children` has run
+    pub fn children(&self, index: NodeIndex) -> impl Iterator<Item = (NodeIndex, &TreeNode)> {
+        self.nodes[index]
+            .children
+            .iter()
+            .map(move |&child| (child, &self.nodes[child]))
+    }
+
+    // add a node below `parent`, the size of a file is added to the exclusive
+    // size of its parent directory right away while the inclusive sizes of
+    // directories are summed up by `compute_totals`
+    pub fn add_node(
+        &mut self,
+        parent: NodeIndex,
+        name: impl Into<Box<OsStr>>,
+        node_type: NodeType,
+        size: u64,
+        apparent_size: Option<u64>,
+    ) -> NodeIndex {
+        let index = self.nodes.len();
+        let is_file = node_type == NodeType::File;
+        if is_file {
+            let parent_node = &mut self.nodes[parent];
+            parent_node.exclusive_size += size;
+            add_apparent(&mut parent_node.exclusive_apparent_size, apparent_size);
+        }
+        self.nodes.push(TreeNode {
+            name: name.into(),
+            parent: Some(parent),
+            children: Vec::new(),
+            node_type,
+            depth: self.nodes[parent].depth + 1,
+            // the totals of a file are its own size
+            size: if is_file { size } else { 0 },
+            apparent_size: if is_file { apparent_size } else { None },
+            exclusive_size: size,
+            exclusive_apparent_size: apparent_size,
+            files: usize::from(is_file),
+        });
+        if let Some(details) = &mut self.details {
+            details.push(NodeDetails::default());
+        }
+        self.nodes[parent].children.push(index);
+        index
+    }
+
+    // add blocks that belong to a node without being a node themselves, like
+    // the special files of a directory
+    pub fn add_size(&mut self, index: NodeIndex, size: u64, apparent_size: Option<u64>) {
+        let node = &mut self.nodes[index];
+        node.exclusive_size += size;
+        add_apparent(&mut node.exclusive_apparent_size, apparent_size);
+    }
+
+    // move the children and the exclusive size of `from` to the empty node
+    // `to`, children may be stored before their parent until `reorder` runs
+    pub(crate) fn move_contents(&mut self, from: NodeIndex, to: NodeIndex) {
+        let children = std::mem::take(&mut self.nodes[from].children);
+        for &child in children.iter() {
+            self.nodes[child].parent = Some(to);
+        }
+        let from_node = &mut self.nodes[from];
+        let (size, apparent_size) = (std::mem::take(&mut from_node.exclusive_size), from_node.exclusive_apparent_size.take());
+        let to_node = &mut self.nodes[to];
+        to_node.children = children;
+        to_node.exclusive_size += size;
+        add_apparent(&mut to_node.exclusive_apparent_size, apparent_size);
+    }
+
+    // store the nodes breadth first in the order of their child lists,
+    // returns the new index of every node by its old one
+    pub(crate) fn reorder(&mut self) -> Vec<NodeIndex> {
+        let mut order: Vec<NodeIndex> = Vec::with_capacity(self.nodes.len());
+        order.push(self.root());
+        let mut next = 0;
+        while let Some(&index) = order.get(next) {
+            order.extend_from_slice(&self.nodes[index].children);
+            next += 1;
+        }
+        debug_assert_eq!(order.len(), self.nodes.len());
+        let mut new_index: Vec<NodeIndex> = vec![0; self.nodes.len()];
+        for (new, &old) in order.iter().enumerate() {
+            new_index[old] = new;
+        }
+        drop(order);
+
+        for node in self.nodes.iter_mut() {
+            node.parent = node.parent.map(|parent| new_index[parent]);
+            for child in node.children.iter_mut() {
+                *child = new_index[*child];
+            }
+        }
+        // every swap puts one node at its place
+        let mut target = new_index.clone();
+        for index in 0..self.nodes.len() {
+            while target[index] != index {
+                let other = target[index];
+                self.nodes.swap(index, other);
+                if let Some(details) = &mut self.details {
+                    details.swap(index, other);
+                }
+                target.swap(index, other);
+            }
+        }
+        for index in 1..self.nodes.len() {
+            let parent = self.nodes[index].parent.unwrap_or(index);
+            self.nodes[index].depth = self.nodes[parent].depth + 1;
+        }
+        new_index
+    }
+
+    // record where a node was listed and its metadata if details are kept
+    pub fn describe(&mut self, index: NodeIndex, node_details: NodeDetails) {
+        if let Some(details) = &mut self.details {
+            details[index] = node_details;
+        }
+    }
+
+    // the details of a node, if they are kept
+    pub fn details(&self, index: NodeIndex) -> Option<&NodeDetails> {
+        self.details.as_ref().map(|details| &details[index])
+    }
+
+    // sum up inclusive sizes bottom up
+    pub fn compute_totals(&mut self) {
+        for node in self.nodes.iter_mut() {
+            node.size = 0;
+            node.apparent_size = None;
+            node.files = usize::from(node.node_type == NodeType::File);
+        }
+        // children are stored after their parents so a reverse pass visits
+        // every node after all of its descendants
+        for index in (0..self.nodes.len()).rev() {
+            let node = &mut self.nodes[index];
+            node.size += node.exclusive_size;
+            add_apparent(&mut node.apparent_size, node.exclusive_apparent_size);
+            let (size, apparent_size, files) = (node.size, node.apparent_size, node.files);
+            let is_directory = node.node_type == NodeType::Directory;
+            if let Some(parent) = node.parent {
+                let parent_node = &mut self.nodes[parent];
+                parent_node.files += files;
+                // the size of a file is already part of the exclusive size of the parent
+                if is_directory {
+                    parent_node.size += size;
+                    add_apparent(&mut parent_node.apparent_size, apparent_size);
+                }
+            }
+        }
+    }
+
+    // sort every child list by size, largest first
+    pub fn sort_children(&mut self) {
+        for index in 0..self.nodes.len() {
+            let mut children = std::mem::take(&mut self.nodes[index].children);
+            children.sort_by(|&a, &b| {
+                let (a, b) = (&self.nodes[a], &self.nodes[b]);
+                b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name))
+            });
+            self.nodes[index].children = children;
+        }
+    }
+
+    // whether `ancestor` is `index` or one of the nodes above it
+    pub fn is_ancestor(&self, ancestor: NodeIndex, index: NodeIndex) -> bool {
+        let mut current = Some(index);
+        while let Some(node_index) = current {
+            if node_index == ancestor {
+                return true;
+            }
+            current = self.nodes[node_index].parent;
+        }
+        false
+    }
+
+    // every directory, largest first by the given size, ties by path order
+    pub fn directories_by_size(&self, ranking: DirectoryRanking) -> Vec<NodeIndex> {
+        let size = |node: &TreeNode| match ranking {
+            DirectoryRanking::Inclusive => node.size,
+            DirectoryRanking::Exclusive => node.exclusive_size,
+        };
+        let mut directories: Vec<NodeIndex> = self
+            .iter()
+            .filter(|(_, node)| node.node_type == NodeType::Directory)
+            .map(|(index, _)| index)
+            .collect();
+        directories.sort_by(|&a, &b| size(&self.nodes[b]).cmp(&size(&self.nodes[a])).then_with(|| a.cmp(&b)));
+        directories
+    }
+
+    pub fn path(&self, index: NodeIndex) -> PathBuf {
+        let mut names: Vec<&OsStr> = Vec::with_capacity(self.nodes[index].depth + 1);
+        let mut current = Some(index);
+        while let Some(node_index) = current {
+            let node = &self.nodes[node_index];
+            names.push(&node.name);
+            current = node.parent;
+        }
+        names.iter().rev().collect()
+    }
+
+    // index of the node at `path`, which has to start with the root path
+    pub fn find(&self, path: &Path) -> Option<NodeIndex> {
+        let relative = path.strip_prefix(&*self.nodes[self.root()].name).ok()?;
+        let mut current = self.root();
+        for component in relative.iter() {
+            current = *self.nodes[current]
+                .children
+                .iter()
+                .find(|&&child| *self.nodes[child].name == *component)?;
+        }
+        Some(current)
+    }
+
+    // flat view keyed by full path, as produced by earlier versions of the walkers
+    pub fn to_map(&self) -> DirectoryScanResult {
+        self.iter()
+            .map(|(index, node)| {
+                (
+                    self.path(index),
+                    NodeResult {
+                        size: node.size,
+                        apparent_size: node.apparent_size,
+                        node_type: node.node_type,
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> ScanTree {
+        let mut tree = ScanTree::new(PathBuf::from("/root"));
+        let root = tree.root();
+        let a = tree.add_node(root, OsStr::new("a"), NodeType::Directory, 0, None);
+        let b = tree.add_node(root, OsStr::new("b"), NodeType::Directory, 0, None);
+        tree.add_node(root, OsStr::new("top"), NodeType::File, 1, Some(1));
+        tree.add_node(a, OsStr::new("small"), NodeType::File, 10, Some(5));
+        let c = tree.add_node(b, OsStr::new("c"), NodeType::Directory, 0, None);
+        tree.add_node(c, OsStr::new("big"), NodeType::File, 100, Some(50));
+        tree.compute_totals();
+        tree.sort_children();
+        tree
+    }
+
+    #[test]
+    fn test_totals() {
+        let tree = sample();
+        let root = tree.get(tree.root());
+        assert_eq!(root.size, 111);
+        assert_eq!(root.apparent_size, Some(56));
+        assert_eq!(root.exclusive_size, 1);
+        let b = tree.find(Path::new("/root/b")).unwrap();
+        assert_eq!(tree.get(b).size, 100);
+        assert_eq!(tree.get(b).exclusive_size, 0);
+        assert_eq!(root.files, 3);
+        assert_eq!(tree.get(b).files, 1);
+    }
+
+    #[test]
+    fn test_details() {
+        let mut tree = sample();
+        let a = tree.find(Path::new("/root/a")).unwrap();
+        let described = NodeDetails { id: (1, 2), position: 3, attributes: NodeAttributes { modified: Some(1), owner: None } };
+        tree.describe(a, described);
+        assert!(tree.details(a).is_none());
+
+        tree.record_details();
+        tree.describe(a, described);
+        let d = tree.add_node(a, OsStr::new("d"), NodeType::Directory, 0, None);
+        assert_eq!(tree.details(a), Some(&described));
+        assert_eq!(tree.details(d), Some(&NodeDetails::default()));
+    }
+
+    #[test]
+    fn test_move_and_reorder() {
+        // `c` is moved from below `b` to the empty `alias` which comes first
+        let mut tree = ScanTree::new(PathBuf::from("/root"));
+        tree.record_details();
+        let root = tree.root();
+        let b = tree.add_node(root, OsStr::new("b"), NodeType::Directory, 0, None);
+        let c = tree.add_node(b, OsStr::new("c"), NodeType::Directory, 4, None);
+        let big = tree.add_node(c, OsStr::new("big"), NodeType::File, 100, None);
+        tree.describe(big, NodeDetails { position: 7, ..NodeDetails::default() });
+        let alias = tree.add_node(root, OsStr::new("alias"), NodeType::Directory, 0, None);
+        tree.move_contents(c, alias);
+        let new_index = tree.reorder();
+        assert_eq!(new_index, vec![0, 1, 3, 4, 2]);
+
+        tree.compute_totals();
+        let moved = tree.find(Path::new("/root/alias/big")).unwrap();
+        assert_eq!(moved, 4);
+        assert_eq!(tree.get(moved).depth, 2);
+        assert_eq!(tree.details(moved).unwrap().position, 7);
+        assert_eq!(tree.get(tree.find(Path::new("/root/alias")).unwrap()).size, 104);
+        assert_eq!(tree.get(tree.find(Path::new("/root/b/c")).unwrap()).size, 0);
+        assert!(tree.iter().all(|(index, node)| node.parent.is_none_or(|parent| parent < index)));
+    }
+
+    #[test]
+    fn test_children_sorted_by_size() {
+        let tree = sample();
+        let names: Vec<&OsStr> = tree.children(tree.root()).map(|(_, node)| &*node.name).collect();
+        assert_eq!(names, vec!["b", "a", "top"]);
+    }
+
+    #[test]
+    fn test_directory_ranking() {
+        let tree = sample();
+        let names = |ranking: DirectoryRanking| -> Vec<PathBuf> {
+            tree.directories_by_size(ranking).into_iter().map(|index| tree.path(index)).collect()
+        };
+        assert_eq!(names(DirectoryRanking::Inclusive), vec![
+            PathBuf::from("/root"),
+            PathBuf::from("/root/b"),
+            PathBuf::from("/root/b/c"),
+            PathBuf::from("/root/a"),
+        ]);
+        // parents holding no files themselves drop behind their children
+        assert_eq!(names(DirectoryRanking::Exclusive), vec![
+            PathBuf::from("/root/b/c"),
+            PathBuf::from("/root/a"),
+            PathBuf::from("/root"),
+            PathBuf::from("/root/b"),
+        ]);
+    }
+
+    #[test]
+    fn test_paths() {
+        let tree = sample();
+        let big = tree.find(Path::new("/root/b/c/big")).unwrap();
+        assert_eq!(tree.path(big), PathBuf::from("/root/b/c/big"));
+        assert_eq!(tree.get(big).depth, 3);
+        assert!(tree.is_ancestor(tree.root(), big));
+        assert!(!tree.is_ancestor(tree.find(Path::new("/root/a")).unwrap(), big));
+        assert_eq!(tree.find(Path::new("/root/missing")), None);
+        assert_eq!(tree.find(Path::new("/elsewhere")), None);
+
+        let map = tree.to_map();
+        assert_eq!(map.len(), tree.len());
+        assert_eq!(map[Path::new("/root/b/c")].size, 100);
+    }
+}
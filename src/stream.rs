@@ -0,0 +1,428 @@
+// streaming scans, every file is emitted as soon as its directory is listed
+// and every directory once all directories below it are done, nothing but the
+// directories still in progress is kept in memory
+//
+// NDJSON schema version 1, one object per line tagged by "type":
+//
+//     scan       first line: schema_version and root
+//     file       path, size, apparent_size and counted (false for another
+//                path of an inode that was already counted)
+//     directory  path, depth, size (including everything below it),
+//                apparent_size, exclusive_size (the directory itself and its
+//                direct files), exclusive_apparent_size, files (counted
+//                direct files) and unreadable
+//     summary    last line: size, apparent_size, files, directories, errors,
+//                unreadable_directories and runtime_seconds
+//
+// sizes are in bytes, apparent sizes are null unless recorded by the size
+// mode, paths that are not valid UTF-8 have invalid bytes replaced by U+FFFD
+// or, with `--escape`, are escaped like in the text output
+//
+// unlike a full scan a hardlinked file is credited to the first of its paths
+// that is listed, which depends on timing when scanning with several threads
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::display::display_path;
+use crate::types::{DirectoryResult, NodeId, ScanError, ScanOperation, ScanOptions, SkippedMount};
+use crate::walker::{ScanContext, Walker};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+// events waiting for the sink, the walkers wait once that many are queued so
+// that a slow consumer does not let memory grow
+const QUEUED_EVENTS: usize = 4096;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum StreamEvent {
+    Scan {
+        schema_version: u32,
+        root: String,
+    },
+    File {
+        path: String,
+        size: u64,
+        apparent_size: Option<u64>,
+        counted: bool,
+    },
+    Directory {
+        path: String,
+        depth: usize,
+        size: u64,
+        apparent_size: Option<u64>,
+        exclusive_size: u64,
+        exclusive_apparent_size: Option<u64>,
+        files: usize,
+        unreadable: bool,
+    },
+    Summary {
+        size: u64,
+        apparent_size: Option<u64>,
+        files: usize,
+        directories: usize,
+        errors: usize,
+        unreadable_directories: usize,
+        runtime_seconds: f64,
+    },
+}
+
+// receives the events of a streaming scan, called on a thread of its own
+pub type EventSink = Box<dyn FnMut(&StreamEvent) + Send>;
+
+// totals of a streaming scan once it is done
+#[derive(Debug)]
+pub struct StreamResult {
+    pub size: u64,
+    pub apparent_size: Option<u64>,
+    pub files: usize,
+    pub directories: usize,
+
+    // mount points that were not crossed, sorted by path
+    pub skipped_mounts: Vec<SkippedMount>,
+
+    // every error encountered during the scan (empty when failing fast)
+    pub errors: Vec<ScanError>,
+}
+
+impl StreamResult {
+    // the last event of a stream
+    pub fn summary(&self, runtime: Duration) -> StreamEvent {
+        StreamEvent::Summary {
+            size: self.size,
+            apparent_size: self.apparent_size,
+            files: self.files,
+            directories: self.directories,
+            errors: self.errors.len(),
+            unreadable_directories: self
+                .errors
+                .iter()
+                .filter(|error| error.operation == ScanOperation::ReadDir)
+                .count(),
+            runtime_seconds: runtime.as_secs_f64(),
+        }
+    }
+}
+
+// queue an event for the sink, which only stops listening once the scan is over
+fn emit(events: &SyncSender<StreamEvent>, event: StreamEvent) {
+    let _ = events.send(event);
+}
+
+fn add_apparent(total: &mut Option<u64>, apparent_size: Option<u64>) {
+    if let Some(apparent_size) = apparent_size {
+        *total.get_or_insert(0) += apparent_size;
+    }
+}
+
+// a directory that was queued but is not done yet
+#[derive(Default)]
+struct Pending {
+    // subdirectories that are not done yet, unknown until the directory is listed
+    remaining: Option<usize>,
+
+    // sizes of the subdirectories that are done
+    size: u64,
+    apparent_size: Option<u64>,
+
+    exclusive_size: u64,
+    exclusive_apparent_size: Option<u64>,
+    files: usize,
+    unreadable: bool,
+}
+
+struct StreamState {
+    pending: HashMap<PathBuf, Pending>,
+
+    // inodes with several links that were counted already
+    counted_links: HashSet<NodeId>,
+
+    // events are built in order under the lock and written by the thread
+    // running the sink, so that no walker waits for the output
+    events: SyncSender<StreamEvent>,
+    size: u64,
+    apparent_size: Option<u64>,
+    files: usize,
+    directories: usize,
+}
+
+// takes the place of the tree of a full scan, see `ScanContext::listed`
+pub(crate) struct Streamer {
+    root_path: PathBuf,
+
+    // paths are escaped as in the text output
+    escape: bool,
+    state: Mutex<StreamState>,
+}
+
+impl Streamer {
+    fn new(root_path: PathBuf, root_size: (u64, Option<u64>), escape: bool, events: SyncSender<StreamEvent>) -> Streamer {
+        emit(&events, StreamEvent::Scan {
+            schema_version: SCHEMA_VERSION,
+            root: display_path(&root_path, escape).into_owned(),
+        });
+        let mut pending = HashMap::new();
+        pending.insert(
+            root_path.clone(),
+            Pending {
+                exclusive_size: root_size.0,
+                exclusive_apparent_size: root_size.1,
+                ..Pending::default()
+            },
+        );
+        Streamer {
+            root_path,
+            escape,
+            state: Mutex::new(StreamState {
+                pending,
+                counted_links: HashSet::new(),
+                events,
+                size: 0,
+                apparent_size: None,
+                files: 0,
+                directories: 0,
+            }),
+        }
+    }
+
+    // emit the files of a listing and register the subdirectories the walker
+    // is about to descend into, before any of them can be done
+    pub(crate) fn listed(&self, directory_path: &Path, directory_result: DirectoryResult, subdirectories: &[&Path]) {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let mut directory = state.pending.remove(directory_path).unwrap_or_default();
+
+        for file in directory_result.files.iter() {
+            let counted = file.links <= 1 || state.counted_links.insert(file.node.id);
+            emit(&state.events, StreamEvent::File {
+                path: self.display(&file.node.path),
+                size: file.size,
+                apparent_size: file.apparent_size,
+                counted,
+            });
+            if counted {
+                directory.exclusive_size += file.size;
+                add_apparent(&mut directory.exclusive_apparent_size, file.apparent_size);
+                directory.files += 1;
+            }
+        }
+        let (special_size, special_apparent_size) = directory_result.counted_special_size();
+        directory.exclusive_size += special_size;
+        add_apparent(&mut directory.exclusive_apparent_size, special_apparent_size);
+        state.files += directory.files;
+
+        let queued: HashSet<&Path> = subdirectories.iter().copied().collect();
+        for subdirectory in directory_result.directories.iter() {
+            if queued.contains(subdirectory.node.path.as_path()) {
+                state.pending.insert(
+                    subdirectory.node.path.clone(),
+                    Pending {
+                        exclusive_size: subdirectory.size,
+                        exclusive_apparent_size: subdirectory.apparent_size,
+                        ..Pending::default()
+                    },
+                );
+            }
+        }
+        directory.remaining = Some(subdirectories.len());
+        state.pending.insert(directory_path.to_path_buf(), directory);
+        if subdirectories.is_empty() {
+            self.complete(state, directory_path.to_path_buf());
+        }
+    }
+
+    // a directory whose contents could not be listed is done right away
+    pub(crate) fn unreadable(&self, directory_path: &Path) {
+        let mut state = self.state.lock().unwrap();
+        let directory = state.pending.entry(directory_path.to_path_buf()).or_default();
+        directory.remaining = Some(0);
+        directory.unreadable = true;
+        self.complete(&mut state, directory_path.to_path_buf());
+    }
+
+    fn display(&self, path: &Path) -> String {
+        display_path(path, self.escape).into_owned()
+    }
+
+    // emit a directory that is done and every ancestor that is done with it
+    fn complete(&self, state: &mut StreamState, mut directory_path: PathBuf) {
+        while let Some(directory) = state.pending.remove(&directory_path) {
+            let size = directory.size + directory.exclusive_size;
+            let mut apparent_size = directory.apparent_size;
+            add_apparent(&mut apparent_size, directory.exclusive_apparent_size);
+            let depth = directory_path
+                .strip_prefix(&self.root_path)
+                .map(|relative| relative.components().count())
+                .unwrap_or(0);
+            emit(&state.events, StreamEvent::Directory {
+                path: self.display(&directory_path),
+                depth,
+                size,
+                apparent_size,
+                exclusive_size: directory.exclusive_size,
+                exclusive_apparent_size: directory.exclusive_apparent_size,
+                files: directory.files,
+                unreadable: directory.unreadable,
+            });
+            state.directories += 1;
+
+            if directory_path == self.root_path {
+                state.size = size;
+                state.apparent_size = apparent_size;
+                return;
+            }
+            let parent_path = match directory_path.parent() {
+                Some(parent_path) => parent_path.to_path_buf(),
+                None => return,
+            };
+            let parent = match state.pending.get_mut(&parent_path) {
+                Some(parent) => parent,
+                None => return,
+            };
+            parent.size += size;
+            add_apparent(&mut parent.apparent_size, apparent_size);
+            match parent.remaining.as_mut() {
+                Some(remaining) => {
+                    *remaining -= 1;
+                    if *remaining > 0 {
+                        return;
+                    }
+                }
+                None => return,
+            }
+            directory_path = parent_path;
+        }
+    }
+}
+
+// scan `root_path` with the given walker handing every event to `sink` as
+// soon as it is known instead of building a tree, paths in the events are
+// escaped like in the text output with `escape`
+pub fn stream<W: Walker>(
+    walker: &W,
+    root_path: PathBuf,
+    options: &ScanOptions,
+    escape: bool,
+    mut sink: EventSink,
+) -> Result<StreamResult, ScanError> {
+    let mut context = ScanContext::new(root_path, options)?;
+    let (events, queued) = sync_channel(QUEUED_EVENTS);
+    // the sink runs until the streamer and with it the sending side of the
+    // queue is dropped, every event is handed to it before this returns
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            for event in queued {
+                sink(&event);
+            }
+        });
+        context.stream = Some(Streamer::new(context.root_path.clone(), context.root_size, escape, events));
+        let errors = walker.walk(&context);
+        let state = context.stream.take().unwrap().state.into_inner().unwrap();
+        let mut errors = errors?;
+        errors.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(StreamResult {
+            size: state.size,
+            apparent_size: state.apparent_size,
+            files: state.files,
+            directories: state.directories,
+            skipped_mounts: context.take_skipped_mounts(),
+            errors,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use crate::test_support::Fixture;
+    use crate::walk::SequentialWalker;
+    use crate::walk_async::ThreadedWalker;
+    use crate::walker::scan;
+
+    #[test]
+    fn test_stream_matches_scan() {
+        let fixture = Fixture::new("stream");
+        fixture
+            .file("a/one", 10)
+            .file("a/b/two", 5000)
+            .file("a/b/c/three", 70000)
+            .dir("empty/nested")
+            .file("top", 4096)
+            .hard_link("a/b/two", "two-link");
+        let options = ScanOptions::default();
+        let full = scan(&SequentialWalker, fixture.root.clone(), &options).unwrap();
+        let total = full.tree.get(full.tree.root()).size;
+
+        for threads in [1, 4] {
+            let events: Arc<Mutex<Vec<StreamEvent>>> = Arc::new(Mutex::new(Vec::new()));
+            let collected = events.clone();
+            let sink: EventSink = Box::new(move |event: &StreamEvent| collected.lock().unwrap().push(event.clone()));
+            let result = stream(&ThreadedWalker { threads }, fixture.root.clone(), &options, false, sink).unwrap();
+            assert_eq!(result.size, total);
+            assert_eq!(result.files, 4);
+            assert_eq!(result.directories, 6);
+
+            // every directory comes after everything below it
+            let events = events.lock().unwrap();
+            let position = |wanted: &Path| {
+                events.iter().position(|event| match event {
+                    StreamEvent::Directory { path, .. } | StreamEvent::File { path, .. } => *path == wanted.to_string_lossy(),
+                    _ => false,
+                })
+            };
+            assert!(position(&fixture.path("a/b/c/three")) < position(&fixture.path("a/b/c")));
+            assert!(position(&fixture.path("a/b/c")) < position(&fixture.path("a/b")));
+            assert!(position(&fixture.path("a")) < position(&fixture.root));
+            assert_eq!(events.iter().filter(|event| matches!(event, StreamEvent::File { counted: false, .. })).count(), 1);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stream_follows_files_once() {
+        let outside = Fixture::new("stream-outside");
+        outside.file("single", 100000);
+        let fixture = Fixture::new("stream-follow");
+        fixture.symlink(outside.path("single"), "link").symlink(outside.path("single"), "link-again");
+        let options = ScanOptions { follow_symlinks: crate::types::FollowSymlinks::Always, ..ScanOptions::default() };
+        let full = scan(&SequentialWalker, fixture.root.clone(), &options).unwrap();
+
+        let sink: EventSink = Box::new(|_: &StreamEvent| {});
+        let result = stream(&SequentialWalker, fixture.root.clone(), &options, false, sink).unwrap();
+        assert_eq!(result.size, full.tree.get(full.tree.root()).size);
+        assert_eq!(result.files, 1);
+    }
+
+    #[test]
+    fn test_stream_escapes_paths() {
+        let fixture = Fixture::new("stream-escape");
+        fixture.file("new\nline", 1);
+        let paths = |escape: bool| {
+            let events: Arc<Mutex<Vec<StreamEvent>>> = Arc::new(Mutex::new(Vec::new()));
+            let collected = events.clone();
+            let sink: EventSink = Box::new(move |event: &StreamEvent| collected.lock().unwrap().push(event.clone()));
+            stream(&SequentialWalker, fixture.root.clone(), &ScanOptions::default(), escape, sink).unwrap();
+            let events = events.lock().unwrap();
+            events
+                .iter()
+                .filter_map(|event| match event {
+                    StreamEvent::File { path, .. } => Some(path.clone()),
+                    _ => None,
+                })
+                .collect::<Vec<String>>()
+        };
+        let path = fixture.path("new\nline").to_string_lossy().into_owned();
+        assert_eq!(paths(false), vec![path.clone()]);
+        assert_eq!(paths(true), vec![path.replace('\n', "\\n")]);
+    }
+}
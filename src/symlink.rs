@@ -0,0 +1,107 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::types::{SymLinkInfo, SymLinkStatus};
+
+// maximum number of links resolved in a row before a chain counts as a loop
+const MAX_HOPS: usize = 40;
+
+// read and classify the symlink at `path`
+pub fn inspect(path: PathBuf, root_path: &Path) -> SymLinkInfo {
+    let target = fs::read_link(&path).unwrap_or_default();
+    let status = match fs::canonicalize(&path) {
+        Ok(resolved) => {
+            let contains_link = resolved.is_dir()
+                && path
+                    .parent()
+                    .and_then(|parent| fs::canonicalize(parent).ok())
+                    .is_some_and(|parent| parent.starts_with(&resolved));
+            if contains_link {
+                SymLinkStatus::Ancestor
+            } else if resolved.starts_with(root_path) {
+                SymLinkStatus::Internal
+            } else {
+                SymLinkStatus::External
+            }
+        }
+        Err(_) => unresolvable(&path),
+    };
+    SymLinkInfo { path, target, status }
+}
+
+// tell a dangling link from a chain of links that never ends
+fn unresolvable(path: &Path) -> SymLinkStatus {
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+    let mut current = path.to_path_buf();
+    for _ in 0..MAX_HOPS {
+        let target = match fs::read_link(&current) {
+            Ok(target) => target,
+            // a missing node or a node that is not a link ends the chain
+            Err(_) => return SymLinkStatus::Broken,
+        };
+        current = match current.parent() {
+            Some(parent) => parent.join(target),
+            None => target,
+        };
+        if !seen.insert(current.clone()) {
+            return SymLinkStatus::Loop;
+        }
+    }
+    SymLinkStatus::Loop
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    use crate::test_support::Fixture;
+    use crate::types::ScanOptions;
+    use crate::walk::SequentialWalker;
+    use crate::walker::scan;
+
+    #[test]
+    fn test_inspect() {
+        let outside = Fixture::new("symlink-target");
+        let fixture = Fixture::new("symlink");
+        fixture
+            .file("dir/file", 1)
+            .symlink("dir/file", "internal")
+            .symlink(&outside.root, "external")
+            .symlink("missing", "broken")
+            .symlink("second", "first")
+            .symlink("first", "second")
+            .symlink("..", "dir/up");
+
+        let status = |relative: &str| inspect(fixture.path(relative), &fixture.root).status;
+        assert_eq!(status("internal"), SymLinkStatus::Internal);
+        assert_eq!(status("external"), SymLinkStatus::External);
+        assert_eq!(status("broken"), SymLinkStatus::Broken);
+        assert_eq!(status("first"), SymLinkStatus::Loop);
+        assert_eq!(status("dir/up"), SymLinkStatus::Ancestor);
+
+        let link = inspect(fixture.path("internal"), &fixture.root);
+        assert!(link.is_relative());
+        assert_eq!(link.target, PathBuf::from("dir/file"));
+    }
+
+    #[test]
+    fn test_inspect_symlinks() {
+        let fixture = Fixture::new("inspect");
+        fixture.file("file", 10).symlink("file", "internal").symlink("missing", "broken");
+        let options = ScanOptions { count_special: true, ..ScanOptions::default() };
+        let counted = scan(&SequentialWalker, fixture.root.clone(), &options).unwrap();
+        // the blocks of the links are counted without resolving them
+        assert!(counted.sym_links.is_empty());
+        assert_eq!(counted.counted_special.len(), 2);
+
+        let options = ScanOptions { inspect_symlinks: true, ..options };
+        let inspected = scan(&SequentialWalker, fixture.root.clone(), &options).unwrap();
+        let links: Vec<(PathBuf, SymLinkStatus)> = inspected.sym_links.iter().map(|link| (link.path.clone(), link.status)).collect();
+        assert_eq!(links, vec![
+            (fixture.path("broken"), SymLinkStatus::Broken),
+            (fixture.path("internal"), SymLinkStatus::Internal),
+        ]);
+        assert_eq!(inspected.tree.to_map(), counted.tree.to_map());
+    }
+}
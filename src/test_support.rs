@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::scan_tree::ScanTree;
+use crate::types::{NodeType, Totals};
+
+static FIXTURES: AtomicUsize = AtomicUsize::new(0);
+
+// temporary directory tree for walker tests, removed again on drop
+pub struct Fixture {
+    pub root: PathBuf,
+}
+
+impl Fixture {
+    pub fn new(name: &str) -> Fixture {
+        let root = std::env::temp_dir().join(format!(
+            "dir-stat-{}-{}-{}",
+            name,
+            std::process::id(),
+            FIXTURES.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        // walkers are handed canonical paths
+        Fixture { root: fs::canonicalize(root).unwrap() }
+    }
+
+    pub fn path(&self, relative: &str) -> PathBuf {
+        self.root.join(relative)
+    }
+
+    pub fn dir(&self, relative: &str) -> &Fixture {
+        fs::create_dir_all(self.path(relative)).unwrap();
+        self
+    }
+
+    pub fn file(&self, relative: &str, len: usize) -> &Fixture {
+        let path = self.path(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, vec![b'x'; len]).unwrap();
+        self
+    }
+
+    pub fn hard_link(&self, original: &str, link: &str) -> &Fixture {
+        fs::hard_link(self.path(original), self.path(link)).unwrap();
+        self
+    }
+
+    // a chain of directories below `relative` that ends in a file, the full
+    // path of the deepest entries is longer than PATH_MAX so they cannot be
+    // reached by path even by root, returns the last directory that can
+    pub fn too_deep(&self, relative: &str) -> PathBuf {
+        let name = "n".repeat(200);
+        let chain = |base: PathBuf| (0..12).fold(base, |path, _| path.join(&name));
+        let reachable = chain(self.path(relative));
+        fs::create_dir_all(&reachable).unwrap();
+        // created apart and moved below the first chain, every path used on
+        // the way stays below the limit
+        let unreachable = chain(self.path("too-deep"));
+        fs::create_dir_all(&unreachable).unwrap();
+        fs::write(unreachable.join("file"), b"x").unwrap();
+        fs::rename(self.path("too-deep"), reachable.join("too-deep")).unwrap();
+        reachable
+    }
+
+    #[cfg(unix)]
+    pub fn symlink(&self, target: impl AsRef<std::path::Path>, link: &str) -> &Fixture {
+        std::os::unix::fs::symlink(target, self.path(link)).unwrap();
+        self
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+// a small tree with files at several depths and two files linked twice
+pub fn sample() -> Fixture {
+    let fixture = Fixture::new("walker");
+    fixture
+        .file("a/one", 10)
+        .file("a/b/two", 5000)
+        .file("a/b/c/three", 70000)
+        .file("d/four", 1)
+        .file("d/e/f/g/five", 123456)
+        .dir("empty/nested")
+        .file("top", 4096)
+        .hard_link("a/b/two", "d/two-link")
+        .hard_link("d/e/f/g/five", "a/five-link");
+    fixture
+}
+
+// a tree wide and deep enough to keep several workers busy
+pub fn wide() -> Fixture {
+    let fixture = Fixture::new("wide");
+    for a in 0..8 {
+        for b in 0..8 {
+            fixture.file(&format!("{}/{}/file", a, b), 10).dir(&format!("{}/{}/c/d", a, b));
+        }
+    }
+    fixture
+}
+
+// inclusive sizes and file counts of every directory
+pub fn directory_totals(tree: &ScanTree) -> BTreeMap<PathBuf, Totals> {
+    tree.iter()
+        .filter(|(_, node)| node.node_type == NodeType::Directory)
+        .map(|(index, node)| {
+            let totals = Totals { size: node.size, apparent_size: node.apparent_size, files: node.files };
+            (tree.path(index), totals)
+        })
+        .collect()
+}
@@ -1,53 +1,699 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::io::ErrorKind;
+use std::path::PathBuf;
 
+use serde::{Deserialize, Serialize};
+
+use crate::filter::PathFilter;
+use crate::mounts::DEFAULT_SKIPPED_FS_TYPES;
+use crate::scan_tree::{NodeIndex, ScanTree};
+
+// (device, inode) on unix, see `node_id` for other platforms
 pub type NodeId = (u64, u64);
 
+#[derive(Serialize, Deserialize)]
 pub struct NodeInfo {
-    pub path: String,
+    pub path: PathBuf,
 
     // tuple of mount id and node id to uniquely identify a file
     pub id: NodeId,
+
+    // position of the entry in the listing of its directory (readdir order)
+    pub position: usize,
+
+    pub attributes: NodeAttributes,
 }
 
+// descriptive metadata of a node that does not affect its size
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct NodeAttributes {
+    // last modification in seconds since the unix epoch
+    pub modified: Option<i64>,
+
+    // user id of the owner, only known on unix
+    pub owner: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct SymLinkInfo {
-    pub path: String,
+    pub path: PathBuf,
+
+    // target as stored in the link, relative to the directory of the link
+    // unless absolute
+    pub target: PathBuf,
+    pub status: SymLinkStatus,
+}
+
+impl SymLinkInfo {
+    pub fn is_relative(&self) -> bool {
+        self.target.is_relative()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymLinkStatus {
+    // the target is inside of the scan root
+    Internal,
+    // the target exists outside of the scan root
+    External,
+    // the target is a directory containing the link, following it loops
+    Ancestor,
+    // the target does not exist
+    Broken,
+    // the link resolves to itself through a chain of links
+    Loop,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct FileInfo {
-    // size of the directory
-    // u64 has a max of ~2 exabytes so u128 should be enough :P
-    pub size: u128,
+    // size of the file as selected by the size mode
+    pub size: u64,
+
+    // logical length of the file, only recorded with `SizeMode::Both`
+    pub apparent_size: Option<u64>,
+
+    // hardlinks to the file on its file system, inside the scan or not, at
+    // least 2 for the target of a followed symlink as other links may lead
+    // to it as well
+    pub links: u64,
 
     pub node: NodeInfo,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct DirectoryInfo {
+    // blocks of the directory itself, not of its entries
+    pub size: u64,
+    pub apparent_size: Option<u64>,
+
     pub node: NodeInfo,
 }
 
-#[derive(Debug)]
-#[derive(PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum NodeType {
     File,
     Directory,
 }
 
-#[derive(Debug)]
+// inclusive sizes and file count of a directory
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Totals {
+    pub size: u64,
+    pub apparent_size: Option<u64>,
+    pub files: usize,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct NodeResult {
-    pub size: u128,
+    pub size: u64,
+    pub apparent_size: Option<u64>,
     pub node_type: NodeType,
 }
 
+// which size of a file is counted
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SizeMode {
+    // blocks allocated on disk (like `du`)
+    Allocated,
+    // logical length of the file (like `du --apparent-size`)
+    Apparent,
+    // allocated size as the primary size with the apparent size recorded alongside
+    Both,
+}
+
+impl std::str::FromStr for SizeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SizeMode, String> {
+        match s {
+            "allocated" => Ok(SizeMode::Allocated),
+            "apparent" => Ok(SizeMode::Apparent),
+            "both" => Ok(SizeMode::Both),
+            _ => Err(format!("unknown size mode '{}'", s)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct DirectoryResult {
     pub files: Vec<FileInfo>,
     pub directories: Vec<DirectoryInfo>,
     pub sym_links: Vec<SymLinkInfo>,
+
+    // entries of the directory that could not be inspected
+    pub errors: Vec<ScanError>,
+
+    // entries pruned by the path filter
+    pub excluded: Excluded,
+
+    // symlinks and special files (sockets, fifos and device nodes) in the
+    // directory whose blocks are counted, only with `ScanOptions::count_special`
+    pub counted_special: Vec<SpecialEntry>,
+
+    // special files of the directory
+    pub special_files: SpecialFiles,
+}
+
+impl DirectoryResult {
+    // blocks of the counted symlinks and special files together
+    pub fn counted_special_size(&self) -> (u64, Option<u64>) {
+        counted_size(&self.counted_special)
+    }
+}
+
+// blocks of a number of counted symlinks and special files together
+pub fn counted_size(entries: &[SpecialEntry]) -> (u64, Option<u64>) {
+    let size = entries.iter().map(|entry| entry.size).sum();
+    let apparent_size = entries.iter().filter_map(|entry| entry.apparent_size).reduce(|a, b| a + b);
+    (size, apparent_size)
+}
+
+// a symlink that is not followed or a special file, which is not a node of
+// the tree but whose blocks are counted for the directory holding it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpecialEntry {
+    pub path: PathBuf,
+    pub id: NodeId,
+    // position of the entry in the listing of its directory (readdir order)
+    pub position: usize,
+    pub size: u64,
+    pub apparent_size: Option<u64>,
+}
+
+// kind of a directory entry that is neither a file, a directory nor a symlink
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SpecialKind {
+    Socket,
+    Fifo,
+    BlockDevice,
+    CharDevice,
+    // anything else the platform knows about (e.g. solaris doors)
+    Other,
 }
 
-pub type DirectoryScanResult = HashMap<String, NodeResult>;
+// special files of a directory counted by kind
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SpecialFiles {
+    pub sockets: usize,
+    pub fifos: usize,
+    pub block_devices: usize,
+    pub char_devices: usize,
+    pub other: usize,
 
-#[derive(Debug)]
+    // blocks of the special files, only part of the totals with
+    // `ScanOptions::count_special`
+    pub size: u64,
+}
+
+impl SpecialFiles {
+    pub fn add(&mut self, kind: SpecialKind, size: u64) {
+        match kind {
+            SpecialKind::Socket => self.sockets += 1,
+            SpecialKind::Fifo => self.fifos += 1,
+            SpecialKind::BlockDevice => self.block_devices += 1,
+            SpecialKind::CharDevice => self.char_devices += 1,
+            SpecialKind::Other => self.other += 1,
+        }
+        self.size += size;
+    }
+
+    pub fn append(&mut self, other: &SpecialFiles) {
+        self.sockets += other.sockets;
+        self.fifos += other.fifos;
+        self.block_devices += other.block_devices;
+        self.char_devices += other.char_devices;
+        self.other += other.other;
+        self.size += other.size;
+    }
+
+    pub fn count(&self) -> usize {
+        self.sockets + self.fifos + self.block_devices + self.char_devices + self.other
+    }
+}
+
+// entries pruned by the path filter, the size is only measured on request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Excluded {
+    pub entries: usize,
+    pub size: Option<u64>,
+    // only recorded with `SizeMode::Both`
+    pub apparent_size: Option<u64>,
+}
+
+impl Excluded {
+    pub fn add(&mut self, other: Excluded) {
+        self.entries += other.entries;
+        if let Some(size) = other.size {
+            *self.size.get_or_insert(0) += size;
+        }
+        if let Some(apparent_size) = other.apparent_size {
+            *self.apparent_size.get_or_insert(0) += apparent_size;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScanOperation {
+    // listing the entries of a directory
+    ReadDir,
+    // reading the next entry of a directory listing
+    ReadEntry,
+    // reading the metadata of a node
+    Metadata,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScanError {
+    pub path: PathBuf,
+    pub operation: ScanOperation,
+    #[serde(with = "error_kind")]
+    pub kind: ErrorKind,
+}
+
+impl ScanError {
+    pub fn new(path: PathBuf, operation: ScanOperation, error: &std::io::Error) -> ScanError {
+        ScanError { path, operation, kind: error.kind() }
+    }
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self.operation {
+            ScanOperation::ReadDir => "cannot read directory",
+            ScanOperation::ReadEntry => "cannot read entry of directory",
+            ScanOperation::Metadata => "cannot access",
+        };
+        write!(f, "{} '{}': {}", action, self.path.display(), self.kind)
+    }
+}
+
+impl std::error::Error for ScanError {}
+
+// `ErrorKind` has no serde support, it is stored by the name of the variant
+mod error_kind {
+    use std::io::ErrorKind;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    const KINDS: [ErrorKind; 13] = [
+        ErrorKind::NotFound,
+        ErrorKind::PermissionDenied,
+        ErrorKind::AlreadyExists,
+        ErrorKind::WouldBlock,
+        ErrorKind::InvalidInput,
+        ErrorKind::InvalidData,
+        ErrorKind::TimedOut,
+        ErrorKind::WriteZero,
+        ErrorKind::Interrupted,
+        ErrorKind::Unsupported,
+        ErrorKind::UnexpectedEof,
+        ErrorKind::OutOfMemory,
+        ErrorKind::Other,
+    ];
+
+    pub fn serialize<S: Serializer>(kind: &ErrorKind, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:?}", kind))
+    }
+
+    // kinds not listed above come back as `Other`
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ErrorKind, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(KINDS
+            .iter()
+            .copied()
+            .find(|kind| format!("{:?}", kind) == name)
+            .unwrap_or(ErrorKind::Other))
+    }
+}
+
+// what a walker does when a directory or node cannot be read
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorPolicy {
+    // record the error in the scan result and keep going
+    Continue,
+    // abort the scan with the first error
+    FailFast,
+    // like `Continue` but also print every error to stderr as it happens
+    Warn,
+}
+
+impl std::str::FromStr for ErrorPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ErrorPolicy, String> {
+        match s {
+            "continue" => Ok(ErrorPolicy::Continue),
+            "fail-fast" => Ok(ErrorPolicy::FailFast),
+            "warn" => Ok(ErrorPolicy::Warn),
+            _ => Err(format!("unknown error policy '{}'", s)),
+        }
+    }
+}
+
+// which paths of a file with several hardlinks inside the scan are credited with its size
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HardlinkPolicy {
+    // the lexicographically first path
+    First,
+    // the path reached first walking the tree depth first in listing order,
+    // as `du` counts
+    Traversal,
+    // the path closest to the root (ties broken lexicographically)
+    Shallowest,
+    // every path gets an equal share of the size
+    Split,
+    // every path is credited with the full size
+    Everywhere,
+}
+
+impl std::str::FromStr for HardlinkPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<HardlinkPolicy, String> {
+        match s {
+            "first" => Ok(HardlinkPolicy::First),
+            "traversal" => Ok(HardlinkPolicy::Traversal),
+            "shallowest" => Ok(HardlinkPolicy::Shallowest),
+            "split" => Ok(HardlinkPolicy::Split),
+            "everywhere" => Ok(HardlinkPolicy::Everywhere),
+            _ => Err(format!("unknown hardlink policy '{}'", s)),
+        }
+    }
+}
+
+// every path inside the scan that links to the same inode
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HardlinkGroup {
+    pub id: NodeId,
+
+    // the path credited with the size comes first (for policies that share
+    // the size it is the one the others are reported as duplicates of)
+    pub paths: Vec<PathBuf>,
+
+    // size of the file itself, counted once
+    pub size: u64,
+    pub apparent_size: Option<u64>,
+}
+
+impl HardlinkGroup {
+    pub fn credited(&self) -> &PathBuf {
+        &self.paths[0]
+    }
+
+    // number of links to the inode inside the scan
+    pub fn links(&self) -> usize {
+        self.paths.len()
+    }
+}
+
+// which symlinks are followed
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FollowSymlinks {
+    // no symlink, not even the root path
+    Never,
+    // only the root path given to the scan
+    Root,
+    // every symlink to a file or directory outside of the scan root, loops
+    // are detected by the id of the target directory, links to nodes inside
+    // the root (and to its ancestors) are never followed as their targets
+    // are counted where they are, only available where node ids identify
+    // files (see `node_id::IDENTIFIES_FILES`) and treated like `Root`
+    // elsewhere
+    Always,
+}
+
+impl std::str::FromStr for FollowSymlinks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FollowSymlinks, String> {
+        match s {
+            "never" => Ok(FollowSymlinks::Never),
+            "root" => Ok(FollowSymlinks::Root),
+            "always" if crate::node_id::IDENTIFIES_FILES => Ok(FollowSymlinks::Always),
+            "always" => Err(String::from("following every symlink is not supported on this platform")),
+            _ => Err(format!("unknown symlink mode '{}'", s)),
+        }
+    }
+}
+
+// size directories are ranked by
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DirectoryRanking {
+    // the directory and everything below it
+    Inclusive,
+    // the directory itself and its direct files, not its subdirectories
+    Exclusive,
+}
+
+impl std::str::FromStr for DirectoryRanking {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DirectoryRanking, String> {
+        match s {
+            "inclusive" => Ok(DirectoryRanking::Inclusive),
+            "exclusive" => Ok(DirectoryRanking::Exclusive),
+            _ => Err(format!("unknown directory ranking '{}'", s)),
+        }
+    }
+}
+
+// how the result of a scan is printed
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    // human readable listings
+    Text,
+    // one JSON document, see `json::SCHEMA_VERSION`
+    Json,
+    // one JSON object per file and directory as soon as it is done, see
+    // `stream::SCHEMA_VERSION`
+    Ndjson,
+    // one row per file and directory with the selected columns
+    Csv,
+    Tsv,
+    // lines like those of `du`
+    Du,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OutputFormat, String> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" => Ok(OutputFormat::Tsv),
+            "du" => Ok(OutputFormat::Du),
+            _ => Err(format!("unknown output format '{}'", s)),
+        }
+    }
+}
+
+// column of a csv or tsv export
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Column {
+    Path,
+    Name,
+    Type,
+    Size,
+    ApparentSize,
+    ExclusiveSize,
+    // number of files of the node and everything below it
+    Files,
+    Depth,
+    Mtime,
+    Owner,
+}
+
+impl Column {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Column::Path => "path",
+            Column::Name => "name",
+            Column::Type => "type",
+            Column::Size => "size",
+            Column::ApparentSize => "apparent-size",
+            Column::ExclusiveSize => "exclusive-size",
+            Column::Files => "files",
+            Column::Depth => "depth",
+            Column::Mtime => "mtime",
+            Column::Owner => "owner",
+        }
+    }
+}
+
+impl std::str::FromStr for Column {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Column, String> {
+        match s {
+            "path" => Ok(Column::Path),
+            "name" => Ok(Column::Name),
+            "type" => Ok(Column::Type),
+            "size" => Ok(Column::Size),
+            "apparent-size" => Ok(Column::ApparentSize),
+            "exclusive-size" => Ok(Column::ExclusiveSize),
+            "files" => Ok(Column::Files),
+            "depth" => Ok(Column::Depth),
+            "mtime" => Ok(Column::Mtime),
+            "owner" => Ok(Column::Owner),
+            _ => Err(format!("unknown column '{}'", s)),
+        }
+    }
+}
+
+// a directory reached through more than one path, e.g. through a bind mount
+// or several followed symlinks, it is only listed and counted at `target`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DirectoryAlias {
+    pub path: PathBuf,
+
+    // path the directory is counted at, the first one in breadth first order
+    pub target: PathBuf,
+
+    // the alias lies inside of its target, following it would never end
+    pub is_loop: bool,
+}
+
+// a mount point the walkers did not descend into
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SkippedMount {
+    pub path: PathBuf,
+
+    // type of the mounted file system if it is known
+    pub fs_type: Option<String>,
+}
+
+// the part of a scan stored on one device
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceUsage {
+    // `st_dev` of the nodes
+    pub device: u64,
+
+    // mount point and type of the file system if the mount table lists it
+    pub mount_point: Option<PathBuf>,
+    pub fs_type: Option<String>,
+
+    // size of the files counted on this device
+    pub size: u64,
+    pub apparent_size: Option<u64>,
+    pub files: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanOptions {
+    pub error_policy: ErrorPolicy,
+    pub size_mode: SizeMode,
+    pub hardlink_policy: HardlinkPolicy,
+
+    // the root path is resolved by the caller, walkers only look at `Always`
+    pub follow_symlinks: FollowSymlinks,
+
+    // do not descend into directories on another device than the root
+    pub one_file_system: bool,
+
+    // file system types whose mount points below the root are skipped without
+    // even being stat'ed, an entry also matches its subtypes (`fuse` matches
+    // `fuse.sshfs`), see `mounts::DEFAULT_SKIPPED_FS_TYPES`
+    pub skip_fs_types: Vec<String>,
+
+    // exclude and include patterns, pruned entries are not even stat'ed
+    pub filter: PathFilter,
+
+    // measure the size of pruned entries (walking excluded directories)
+    pub measure_excluded: bool,
+
+    // count the blocks of symlinks that are not followed and of special files,
+    // which are attributed to the directory holding them
+    pub count_special: bool,
+
+    // classify every symlink for `ScanResult::sym_links`, otherwise a link is
+    // only resolved when `FollowSymlinks::Always` has to know its target
+    pub inspect_symlinks: bool,
+
+    // keep the listing position and metadata of every node, see
+    // `ScanTree::details`
+    pub record_details: bool,
+}
+
+impl Default for ScanOptions {
+    fn default() -> ScanOptions {
+        ScanOptions {
+            error_policy: ErrorPolicy::Warn,
+            size_mode: SizeMode::Allocated,
+            hardlink_policy: HardlinkPolicy::Shallowest,
+            follow_symlinks: FollowSymlinks::Root,
+            one_file_system: false,
+            skip_fs_types: DEFAULT_SKIPPED_FS_TYPES.iter().map(|fs_type| fs_type.to_string()).collect(),
+            filter: PathFilter::default(),
+            measure_excluded: false,
+            count_special: false,
+            inspect_symlinks: false,
+            record_details: false,
+        }
+    }
+}
+
+pub type DirectoryScanResult = HashMap<PathBuf, NodeResult>;
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ScanResult {
-    pub result: DirectoryScanResult,
+    // every counted node below (and including) the root, use `tree.to_map()`
+    // for a flat view keyed by path
+    pub tree: ScanTree,
+
+    // paths of hardlinked files other than the one the file is attributed to,
+    // with the full size of the file
     pub double_count: DirectoryScanResult,
+
+    // files linked more than once inside the scan, largest first
+    pub hardlinks: Vec<HardlinkGroup>,
+
+    // every symlink inside the scan, sorted by path
+    pub sym_links: Vec<SymLinkInfo>,
+
+    // mount points that were not crossed, sorted by path
+    pub skipped_mounts: Vec<SkippedMount>,
+
+    // directories reached more than once, sorted by path
+    pub directory_aliases: Vec<DirectoryAlias>,
+
+    // totals split by device, largest first
+    pub devices: Vec<DeviceUsage>,
+
+    // entries pruned by the path filter
+    pub excluded: Excluded,
+
+    // directories holding special files, sorted by path
+    pub special_files: Vec<(PathBuf, SpecialFiles)>,
+
+    // counted symlinks and special files by the directory node holding them,
+    // only with `ScanOptions::count_special`
+    pub counted_special: Vec<(NodeIndex, SpecialEntry)>,
+
+    // every error encountered during the scan (empty when failing fast)
+    pub errors: Vec<ScanError>,
+}
+
+impl ScanResult {
+    // number of directories whose contents could not be listed
+    pub fn unreadable_directories(&self) -> usize {
+        self.errors
+            .iter()
+            .filter(|error| error.operation == ScanOperation::ReadDir)
+            .count()
+    }
 }
\ No newline at end of file
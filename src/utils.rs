@@ -1,3 +1,5 @@
+pub use dir_stat::display::display_path;
+
 const DISPLAY_PREFIX: usize = 3;
 
 fn display_bytes(size: f64, prefix: &str) -> String {
@@ -10,27 +12,38 @@ fn to_prefix<const COUNT: usize>(value: i128, prefixes: [&str; COUNT], threshold
     for (power, prefix) in prefixes.iter().enumerate() {
         let rounding_upper = power + 1;
         let rounding_decimal = if power == 0 { 0 } else { power - 1 };
-        if (value.abs() as u128) < thresholds[rounding_upper] {
+        if value.unsigned_abs() < thresholds[rounding_upper] {
             let truncated_prefixed_bytes = value as f64 / thresholds[rounding_decimal] as f64;
             let decimal_divisor = thresholds[power - rounding_decimal] as f64;
-            return display_bytes(truncated_prefixed_bytes as f64 / decimal_divisor as f64, prefix);
+            return display_bytes(truncated_prefixed_bytes / decimal_divisor, prefix);
         }
     }
-    return display_bytes((value >> (prefixes.len() * 10)) as f64, prefixes[prefixes.len() - 1]);
+    display_bytes((value >> (prefixes.len() * 10)) as f64, prefixes[prefixes.len() - 1])
 }
 
 const BINARY_PREFIXES: [&str; 7] = ["", "Ki", "Mi", "Gi", "Ti", "Pi", "Ei"];
 const BINARY_THRESHOLDS: [u128; 7] = [1, 1 << 10, 1 << 20, 1 << 30, 1 << 40, 1 << 50, 1 << 60];
 pub fn to_binary_prefix(size_in_bytes: i128) -> String {
-    return to_prefix(size_in_bytes, BINARY_PREFIXES, BINARY_THRESHOLDS);
+    to_prefix(size_in_bytes, BINARY_PREFIXES, BINARY_THRESHOLDS)
 }
 
 const DECIMAL_PREFIXES: [&str; 7] = ["", "K", "M", "G", "T", "P", "E"];
 const DECIMAL_THRESHOLDS: [u128; 7] = [1, 1_000, 1_000_000, 1_000_000_000, 1_000_000_000_000, 1_000_000_000_000_000, 1_000_000_000_000_000_000];
 pub fn to_decimal_prefix(size_in_bytes: i128) -> String {
-    return to_prefix(size_in_bytes, DECIMAL_PREFIXES, DECIMAL_THRESHOLDS);
+    to_prefix(size_in_bytes, DECIMAL_PREFIXES, DECIMAL_THRESHOLDS)
 }
 
+// allocated size followed by the apparent size when both are recorded
+pub fn display_size(size: u64, apparent_size: Option<u64>) -> String {
+    match apparent_size {
+        Some(apparent_size) => format!(
+            "{} (apparent {})",
+            to_decimal_prefix(i128::from(size)),
+            to_decimal_prefix(i128::from(apparent_size))
+        ),
+        None => to_decimal_prefix(i128::from(size)),
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -68,4 +81,4 @@ mod tests {
         assert_eq!(to_decimal_prefix((DECIMAL_THRESHOLDS[3] - DECIMAL_THRESHOLDS[2] + DECIMAL_THRESHOLDS[1]) as i128), "999.001 MB", "GB - KB");
         assert_eq!(to_decimal_prefix((DECIMAL_THRESHOLDS[3] - DECIMAL_THRESHOLDS[2]) as i128), "999 MB", "GB - MB");
     }
-}
\ No newline at end of file
+}
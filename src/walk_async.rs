@@ -1,148 +1,245 @@
-use std::collections::{HashMap, HashSet, VecDeque};
-use std::path::Path;
-use std::sync::{Arc, Mutex, RwLock};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
 use std::thread;
 
 use crate::process_directory::process_directory;
-use crate::types::{NodeResult, NodeId, NodeType, ScanResult};
-
-pub fn process_dir_threaded(root_path_str: String, threads: u8) -> ScanResult {
-    // number of currently processing nodes
-    // (this value will be used in threads when waiting for completion of traversal)
-    let current_running_nodes = Arc::new(RwLock::new(0));
-
-    // set of processed inode ids
-    // if a currently processed node already exists in this set then we skip it
-    // this happens when processing hard links thus only the first encounter of
-    // an inode is processed which could lead to inconsistency between runs
-    let processed_inode_ids: Arc<RwLock<HashSet<NodeId>>> = Arc::new(RwLock::new(HashSet::new()));
-
-    // queue of nodes to process
-    let dir_queue: Arc<RwLock<VecDeque<String>>> = Arc::new(RwLock::new(VecDeque::new()));
-
-    // result to be displayed
-    let scan: Arc<Mutex<ScanResult>> = Arc::new(Mutex::new(ScanResult {
-        result: HashMap::new(),
-        double_count: HashMap::new(),
-    }));
-
-    // recursive iteration of all files/directories starting with root directory
-
-    // add the root resolved path (after navigating symlink) to the queue
-    dir_queue
-        .write()
-        .expect("Failed to initialize work queue")
-        .push_front(root_path_str);
-
-    // start n threads of node process
-    let mut children = vec![];
-
-    for _ in 1..threads {
-        let current_running_nodes_arc = Arc::clone(&current_running_nodes);
-        let dir_queue_arc = Arc::clone(&dir_queue);
-        let processed_inode_ids_arc = Arc::clone(&processed_inode_ids);
-        let scan_arc = Arc::clone(&scan);
-        children.push(thread::spawn(move || {
-            loop {
-                let other_running_nodes = *current_running_nodes_arc.read().unwrap();
-                let next_dir = dir_queue_arc.write().unwrap().pop_back();
-                match next_dir {
-                    None => {
-                        if other_running_nodes == 0 {
-                            // if the queue is empty and there are no other nodes currently running, then quit
-                            break;
-                        }
-                    }
-                    Some(directory_path) => {
-                        *current_running_nodes_arc.write().unwrap() += 1;
+use crate::types::{ScanError, ScanOptions, ScanResult};
+use crate::walker::{scan, DirectoryTask, ScanContext, Walker};
+
+// directory to process
+type Task = DirectoryTask;
+
+// one deque per worker, a worker pushes and pops at the back of its own deque
+// (depth first, which keeps the deques short) and steals from the front of
+// the others (the oldest and usually largest subtrees) once its own runs dry
+struct WorkQueues {
+    deques: Vec<Mutex<VecDeque<Task>>>,
+
+    // number of tasks waiting in any deque
+    queued: AtomicUsize,
+
+    // number of tasks queued or being processed, the traversal is complete
+    // once this drops to zero
+    pending: AtomicUsize,
+
+    // set once the traversal is complete or aborted
+    done: AtomicBool,
+
+    // idle workers park on this condition variable until work is pushed
+    // or the traversal is done
+    sleep: Mutex<()>,
+    wake: Condvar,
+}
+
+impl WorkQueues {
+    fn new(workers: usize) -> WorkQueues {
+        WorkQueues {
+            deques: (0..workers).map(|_| Mutex::new(VecDeque::new())).collect(),
+            queued: AtomicUsize::new(0),
+            pending: AtomicUsize::new(0),
+            done: AtomicBool::new(false),
+            sleep: Mutex::new(()),
+            wake: Condvar::new(),
+        }
+    }
+
+    fn push(&self, worker: usize, tasks: Vec<Task>) {
+        if tasks.is_empty() {
+            return;
+        }
+        let count = tasks.len();
+        self.pending.fetch_add(count, Ordering::SeqCst);
+        self.deques[worker].lock().unwrap().extend(tasks);
+        self.queued.fetch_add(count, Ordering::SeqCst);
+
+        // notifying while holding the sleep lock guarantees that a worker which
+        // has just seen no queued tasks is already waiting
+        let _sleep = self.sleep.lock().unwrap();
+        if count > 1 {
+            self.wake.notify_all();
+        } else {
+            self.wake.notify_one();
+        }
+    }
+
+    fn pop(&self, worker: usize) -> Option<Task> {
+        // the own deque is unlocked again before stealing, holding it while
+        // locking another one deadlocks with a worker stealing the other way
+        let own = self.deques[worker].lock().unwrap().pop_back();
+        let task = own.or_else(|| {
+            let workers = self.deques.len();
+            (1..workers)
+                .map(|offset| (worker + offset) % workers)
+                .find_map(|victim| self.deques[victim].lock().unwrap().pop_front())
+        });
+        if task.is_some() {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+        }
+        task
+    }
+
+    // wait for a task, returns None once the traversal is done
+    fn next(&self, worker: usize) -> Option<Task> {
+        loop {
+            if self.done.load(Ordering::SeqCst) {
+                return None;
+            }
+            if let Some(task) = self.pop(worker) {
+                return Some(task);
+            }
+            let sleep = self.sleep.lock().unwrap();
+            if self.queued.load(Ordering::SeqCst) == 0 && !self.done.load(Ordering::SeqCst) {
+                drop(self.wake.wait(sleep).unwrap());
+            }
+        }
+    }
+
+    // mark a task as processed, after all of its subdirectories were pushed
+    fn complete(&self) {
+        if self.pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.finish();
+        }
+    }
+
+    // wake up and stop all workers
+    fn finish(&self) {
+        self.done.store(true, Ordering::SeqCst);
+        let _sleep = self.sleep.lock().unwrap();
+        self.wake.notify_all();
+    }
+}
 
-                        let directory_result = match process_directory(&directory_path) {
+// lists directories on a number of worker threads which steal work from each
+// other, every worker collects its own errors which are merged at the end
+pub struct ThreadedWalker {
+    pub threads: u8,
+}
+
+impl Walker for ThreadedWalker {
+    fn walk(&self, context: &ScanContext) -> Result<Vec<ScanError>, ScanError> {
+        let workers = usize::from(self.threads.max(1));
+
+        // first error of a fail-fast scan, all workers stop once this is set
+        let failure: Mutex<Option<ScanError>> = Mutex::new(None);
+
+        // add the root resolved path (after navigating symlink) to the first queue
+        let queues = WorkQueues::new(workers);
+        queues.push(0, vec![context.root_task()]);
+
+        let mut errors = Vec::new();
+        thread::scope(|scope| {
+            // start n threads of node process
+            let mut children = vec![];
+
+            for worker in 0..workers {
+                let queues = &queues;
+                let failure = &failure;
+                children.push(scope.spawn(move || {
+                    let mut partial = Vec::new();
+                    let fail = |errors: &mut Vec<ScanError>, error: ScanError| {
+                        if let Err(error) = context.record_error(errors, error) {
+                            failure.lock().unwrap().get_or_insert(error);
+                            queues.finish();
+                        }
+                    };
+                    while let Some(task) = queues.next(worker) {
+                        let mut directory_result = match process_directory(&task.path, context) {
                             Ok(r) => r,
-                            Err(_) => {
-                                // TODO: handle access errors
-                                *current_running_nodes_arc.write().unwrap() -= 1;
+                            Err(error) => {
+                                context.unreadable(&task.path);
+                                fail(&mut partial, error);
+                                queues.complete();
                                 continue;
                             }
                         };
-
-                        let mut unprocessed_directories: VecDeque<String> = VecDeque::new();
-                        for directory in directory_result.directories {
-                            if !processed_inode_ids_arc
-                                .read()
-                                .unwrap()
-                                .contains(&directory.node.id)
-                            {
-                                unprocessed_directories.push_back(directory.node.path);
-                            }
-                        }
-                        dir_queue_arc
-                            .write()
-                            .unwrap()
-                            .append(&mut unprocessed_directories);
-
-                        let mut scan_write = scan_arc.lock().unwrap();
-                        let mut directory_size: u128 = 0;
-                        for file in directory_result.files {
-                            if !processed_inode_ids_arc
-                                .read()
-                                .unwrap()
-                                .contains(&file.node.id)
-                            {
-                                processed_inode_ids_arc
-                                    .write()
-                                    .unwrap()
-                                    .insert(file.node.id);
-                                scan_write.result.insert(
-                                    file.node.path,
-                                    NodeResult {
-                                        size: file.size,
-                                        node_type: NodeType::File,
-                                    },
-                                );
-                                directory_size += file.size;
-                            } else {
-                                scan_write.double_count.insert(
-                                    file.node.path,
-                                    NodeResult {
-                                        size: file.size,
-                                        node_type: NodeType::File,
-                                    },
-                                );
-                            }
+                        for error in directory_result.errors.drain(..) {
+                            fail(&mut partial, error);
                         }
 
-                        // add directory size to all parent directories
-                        for ancestor in Path::new(&directory_path)
-                            .ancestors()
-                            .map(|ancestor| String::from(ancestor.to_str().unwrap()))
-                        {
-                            match scan_write.result.get_mut(&ancestor) {
-                                Some(node) => {
-                                    node.size += directory_size;
-                                }
-                                None => {
-                                    scan_write.result.insert(
-                                        ancestor,
-                                        NodeResult {
-                                            size: directory_size,
-                                            node_type: NodeType::Directory,
-                                        },
-                                    );
-                                }
-                            }
-                        }
-
-                        *current_running_nodes_arc.write().unwrap() -= 1;
+                        queues.push(worker, context.listed(task, directory_result));
+                        queues.complete();
                     }
-                }
+                    partial
+                }));
             }
-        }));
+
+            for child in children {
+                // wait for all worker threads to end
+                errors.extend(child.join().unwrap());
+            }
+        });
+
+        if let Some(error) = failure.into_inner().unwrap() {
+            return Err(error);
+        }
+        Ok(errors)
     }
+}
+
+pub fn process_dir_threaded(root_path: PathBuf, threads: u8, options: &ScanOptions) -> Result<ScanResult, ScanError> {
+    scan(&ThreadedWalker { threads }, root_path, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use crate::test_support::{directory_totals, wide};
+    use crate::types::{ErrorPolicy, HardlinkPolicy, SizeMode};
+    use crate::walk::SequentialWalker;
 
-    for child in children {
-        // wait for all worker threads to end
-        let _ = child.join().unwrap();
+    fn task(path: &str) -> Task {
+        DirectoryTask {
+            path: PathBuf::from(path),
+            ancestors: Arc::new([(0, 0)]),
+            index: 0,
+        }
     }
 
-    return Arc::try_unwrap(scan).unwrap().into_inner().unwrap();
+    #[test]
+    fn test_work_stealing() {
+        let queues = WorkQueues::new(2);
+        queues.push(0, vec![task("a"), task("b"), task("c")]);
+        // the owner goes depth first, a thief takes the oldest task
+        assert_eq!(queues.next(0).unwrap().path, PathBuf::from("c"));
+        assert_eq!(queues.next(1).unwrap().path, PathBuf::from("a"));
+        assert_eq!(queues.next(1).unwrap().path, PathBuf::from("b"));
+        queues.complete();
+        queues.complete();
+        assert!(!queues.done.load(Ordering::SeqCst));
+        // the traversal is done once every task is complete
+        queues.complete();
+        assert!(queues.next(0).is_none());
+        assert!(queues.next(1).is_none());
+    }
+
+    #[test]
+    fn test_fail_fast_stops_all_workers() {
+        let fixture = wide();
+        fixture.too_deep("0/0/c");
+        let options = ScanOptions { error_policy: ErrorPolicy::FailFast, ..ScanOptions::default() };
+        for threads in [2, 4, 8] {
+            // returning at all means every worker was woken up and stopped
+            let error = scan(&ThreadedWalker { threads }, fixture.root.clone(), &options).unwrap_err();
+            assert!(error.path.starts_with(fixture.path("0/0/c")));
+        }
+    }
+
+    #[test]
+    fn test_worker_totals() {
+        let fixture = wide();
+        fixture.hard_link("0/0/file", "1/1/c/link").hard_link("2/2/file", "2/2/c/d/link");
+        for hardlink_policy in [HardlinkPolicy::Shallowest, HardlinkPolicy::Everywhere, HardlinkPolicy::Split] {
+            let options = ScanOptions { hardlink_policy, size_mode: SizeMode::Both, ..ScanOptions::default() };
+            let expected = scan(&SequentialWalker, fixture.root.clone(), &options).unwrap();
+            for threads in [1, 2, 4, 8] {
+                let actual = scan(&ThreadedWalker { threads }, fixture.root.clone(), &options).unwrap();
+                assert_eq!(directory_totals(&actual.tree), directory_totals(&expected.tree), "{} threads", threads);
+            }
+        }
+    }
 }
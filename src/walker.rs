@@ -0,0 +1,860 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::scan_tree::{NodeDetails, NodeIndex, ScanTree};
+use crate::stream::Streamer;
+use crate::hardlink::attribute;
+use crate::mounts::{matches_fs_type, mount_of, mount_of_device, read_mounts, MountInfo};
+use crate::file_size::sizes;
+use crate::node_id::{attributes, node_id};
+use crate::types::{counted_size, DeviceUsage, DirectoryAlias, DirectoryInfo, DirectoryResult, ErrorPolicy, Excluded, FileInfo, HardlinkPolicy, NodeId, NodeInfo, NodeResult, NodeType, ScanError, ScanOperation, ScanOptions, ScanResult, SkippedMount, SpecialEntry, SpecialFiles, SymLinkInfo};
+
+// a traversal strategy, walkers only decide in which order and on which
+// thread directories are listed while everything that decides what ends up
+// in the result lives in this module so that every strategy agrees
+pub trait Walker {
+    // list the root and every directory below it that `ScanContext::listed`
+    // returns, the errors recorded on the way are returned
+    fn walk(&self, context: &ScanContext) -> Result<Vec<ScanError>, ScanError>;
+}
+
+// a directory for a walker to list, `ancestors` are the ids of the directories
+// the walker came through from the root down to the directory itself
+pub struct DirectoryTask {
+    pub path: PathBuf,
+    pub ancestors: Arc<[NodeId]>,
+
+    // node of the directory in the tree, unused when streaming
+    pub index: NodeIndex,
+}
+
+impl DirectoryTask {
+    pub fn id(&self) -> NodeId {
+        self.ancestors[self.ancestors.len() - 1]
+    }
+
+    fn subdirectory(&self, directory: &DirectoryInfo, index: NodeIndex) -> DirectoryTask {
+        DirectoryTask {
+            path: directory.node.path.clone(),
+            ancestors: self.ancestors.iter().copied().chain([directory.node.id]).collect(),
+            index,
+        }
+    }
+}
+
+// what becomes of a directory found in a listing
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Subdirectory {
+    // the walker lists it next
+    Descended,
+
+    // reached before, by the walker or while measuring an excluded entry
+    Visited,
+
+    // a mount point skipped by -x, left out like du does
+    Skipped,
+}
+
+// state shared by every directory listing of one scan
+pub struct ScanContext {
+    pub root_path: PathBuf,
+    pub options: ScanOptions,
+
+    // device of the root directory
+    pub root_device: u64,
+
+    // id and blocks of the root directory itself
+    root_id: NodeId,
+    pub(crate) root_size: (u64, Option<u64>),
+
+    // receives the listings instead of the tree when streaming
+    pub(crate) stream: Option<Streamer>,
+
+    // the tree every listing is added to
+    builder: Mutex<TreeBuilder>,
+
+    // ids of every directory handed to the walker so far, a directory that
+    // is reached a second time (e.g. through a followed symlink) is not
+    // listed again which also breaks symlink cycles
+    visited_directories: Mutex<HashSet<NodeId>>,
+
+    // ids of the hardlinked files measured below excluded entries
+    measured_files: Mutex<HashSet<NodeId>>,
+
+    // mount table of the system
+    pub mounts: Vec<MountInfo>,
+
+    // mount points below the root whose file system type is skipped
+    pub(crate) skipped_mount_points: HashMap<PathBuf, String>,
+
+    // devices whose file system type is skipped, other than the one of the
+    // root, by the type of their file system
+    pub(crate) skipped_devices: HashMap<u64, String>,
+
+    // mount points that were not descended into
+    skipped_mounts: Mutex<Vec<SkippedMount>>,
+}
+
+impl ScanContext {
+    pub fn new(root_path: PathBuf, options: &ScanOptions) -> Result<ScanContext, ScanError> {
+        let metadata = fs::metadata(&root_path)
+            .map_err(|e| ScanError::new(root_path.clone(), ScanOperation::Metadata, &e))?;
+        let root_id = node_id(&root_path, &metadata);
+        let mut visited_directories = HashSet::new();
+        visited_directories.insert(root_id);
+        let root_size = sizes(&metadata, options.size_mode);
+        let root_details = NodeDetails { id: root_id, position: 0, attributes: attributes(&metadata) };
+        // the traversal policy credits hardlinks by listing positions
+        let record_details = options.record_details || options.hardlink_policy == HardlinkPolicy::Traversal;
+        let builder = TreeBuilder::new(root_path.clone(), root_size, root_details, record_details);
+
+        // later entries mount over earlier ones on the same mount point
+        let mounts = read_mounts();
+        let mut skipped_mount_points: HashMap<PathBuf, String> = HashMap::new();
+        let mut skipped_devices: HashMap<u64, String> = HashMap::new();
+        for mount in mounts.iter() {
+            if mount.device != root_id.0 && matches_fs_type(&mount.fs_type, &options.skip_fs_types) {
+                skipped_devices.insert(mount.device, mount.fs_type.clone());
+            }
+            if mount.mount_point == root_path || !mount.mount_point.starts_with(&root_path) {
+                continue;
+            }
+            if matches_fs_type(&mount.fs_type, &options.skip_fs_types) {
+                skipped_mount_points.insert(mount.mount_point.clone(), mount.fs_type.clone());
+            } else {
+                skipped_mount_points.remove(&mount.mount_point);
+            }
+        }
+
+        Ok(ScanContext {
+            root_path,
+            options: options.clone(),
+            root_device: root_id.0,
+            root_id,
+            root_size,
+            stream: None,
+            builder: Mutex::new(builder),
+            visited_directories: Mutex::new(visited_directories),
+            measured_files: Mutex::new(HashSet::new()),
+            mounts,
+            skipped_mount_points,
+            skipped_devices,
+            skipped_mounts: Mutex::new(Vec::new()),
+        })
+    }
+
+    // the task a walker starts with
+    pub(crate) fn root_task(&self) -> DirectoryTask {
+        DirectoryTask {
+            path: self.root_path.clone(),
+            ancestors: Arc::new([self.root_id]),
+            index: 0,
+        }
+    }
+
+    // what becomes of a directory found in a listing, which is only decided
+    // once for every path the directory is reached at
+    fn subdirectory(&self, directory: &DirectoryInfo) -> Subdirectory {
+        if self.options.one_file_system && directory.node.id.0 != self.root_device {
+            let fs_type = mount_of(&self.mounts, &directory.node.path).map(|mount| mount.fs_type.clone());
+            self.skipped_mounts.lock().unwrap().push(SkippedMount {
+                path: directory.node.path.clone(),
+                fs_type,
+            });
+            return Subdirectory::Skipped;
+        }
+        if self.first_visit(directory.node.id) {
+            Subdirectory::Descended
+        } else {
+            Subdirectory::Visited
+        }
+    }
+
+    // whether the directory `id` was not reached before, by the walker or
+    // while measuring an excluded entry
+    pub(crate) fn first_visit(&self, id: NodeId) -> bool {
+        self.visited_directories.lock().unwrap().insert(id)
+    }
+
+    // whether the hardlinked file `id` below an excluded entry was not
+    // measured before
+    pub(crate) fn first_measured(&self, id: NodeId) -> bool {
+        self.measured_files.lock().unwrap().insert(id)
+    }
+
+    // whether `path` is a mount point of a skipped file system type, checked
+    // before the node is stat'ed as that alone can hang on a network mount
+    pub(crate) fn skip_mount_point(&self, path: &Path) -> bool {
+        if self.skipped_mount_points.is_empty() {
+            return false;
+        }
+        match self.skipped_mount_points.get(path) {
+            Some(fs_type) => {
+                self.skipped_mounts.lock().unwrap().push(SkippedMount {
+                    path: path.to_path_buf(),
+                    fs_type: Some(fs_type.clone()),
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    // whether the node at `path` is on a device of a skipped file system
+    // type, which catches the mount points `skip_mount_point` cannot know by
+    // their path, e.g. those reached through a followed symlink
+    pub(crate) fn skip_device(&self, path: &Path, device: u64) -> bool {
+        if self.skipped_devices.is_empty() {
+            return false;
+        }
+        match self.skipped_devices.get(&device) {
+            Some(fs_type) => {
+                self.skipped_mounts.lock().unwrap().push(SkippedMount {
+                    path: path.to_path_buf(),
+                    fs_type: Some(fs_type.clone()),
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    // add the listing of a directory to the tree or hand it to the stream,
+    // returns the subdirectories the walker descends into next in the order
+    // they were listed
+    pub(crate) fn listed(&self, task: DirectoryTask, directory_result: DirectoryResult) -> Vec<DirectoryTask> {
+        if let Some(stream) = &self.stream {
+            let subdirectories: Vec<DirectoryTask> = directory_result
+                .directories
+                .iter()
+                .filter(|directory| self.subdirectory(directory) == Subdirectory::Descended)
+                .map(|directory| task.subdirectory(directory, 0))
+                .collect();
+            let paths: Vec<&Path> = subdirectories.iter().map(|subdirectory| subdirectory.path.as_path()).collect();
+            stream.listed(&task.path, directory_result, &paths);
+            return subdirectories;
+        }
+
+        // the subdirectories are decided on under the lock of the tree, so a
+        // directory that two workers reach at once is counted where it is
+        // added first and is an alias everywhere else
+        let mut builder = self.builder.lock().unwrap();
+        let kinds: Vec<Subdirectory> = directory_result.directories.iter().map(|directory| self.subdirectory(directory)).collect();
+        builder.add_listing(&task, directory_result, &kinds)
+    }
+
+    // a directory the walker could not list
+    pub(crate) fn unreadable(&self, directory_path: &Path) {
+        if let Some(stream) = &self.stream {
+            stream.unreadable(directory_path);
+        }
+    }
+
+    // mount points that were not descended into, sorted by path
+    pub(crate) fn take_skipped_mounts(&self) -> Vec<SkippedMount> {
+        let mut skipped_mounts = std::mem::take(&mut *self.skipped_mounts.lock().unwrap());
+        skipped_mounts.sort_by(|a, b| a.path.cmp(&b.path));
+        skipped_mounts
+    }
+
+    // apply the error policy, failing the scan or recording the error
+    pub(crate) fn record_error(&self, errors: &mut Vec<ScanError>, error: ScanError) -> Result<(), ScanError> {
+        record_error(errors, error, self.options.error_policy)
+    }
+}
+
+// scan `root_path` with the given walker
+pub fn scan<W: Walker>(walker: &W, root_path: PathBuf, options: &ScanOptions) -> Result<ScanResult, ScanError> {
+    let context = ScanContext::new(root_path, options)?;
+    scan_context(walker, context)
+}
+
+// scan with a prepared context
+pub(crate) fn scan_context<W: Walker>(walker: &W, context: ScanContext) -> Result<ScanResult, ScanError> {
+    let errors = walker.walk(&context)?;
+    let skipped_mounts = context.take_skipped_mounts();
+    let mut scan = build(context, errors);
+    scan.skipped_mounts = skipped_mounts;
+    Ok(scan)
+}
+
+// apply the error policy, failing the scan or recording the error
+fn record_error(errors: &mut Vec<ScanError>, error: ScanError, policy: ErrorPolicy) -> Result<(), ScanError> {
+    match policy {
+        ErrorPolicy::FailFast => return Err(error),
+        ErrorPolicy::Warn => eprintln!("dir-stat: {}", error),
+        ErrorPolicy::Continue => {}
+    }
+    errors.push(error);
+    Ok(())
+}
+
+// name of a node below a listed directory, which always has a final component
+fn file_name(path: &Path) -> &OsStr {
+    path.file_name().unwrap_or(path.as_os_str())
+}
+
+fn describe(node: &NodeInfo) -> NodeDetails {
+    NodeDetails { id: node.id, position: node.position, attributes: node.attributes }
+}
+
+// the tree of a scan while it is built, every listing is added to it as soon
+// as a walker hands it over so that nothing but the tree is kept while
+// walking, entries that are not nodes are kept by their file name below the
+// node of their directory, `build` puts the tree into its final order
+struct TreeBuilder {
+    tree: ScanTree,
+
+    // usage per device
+    devices: HashMap<u64, DeviceUsage>,
+
+    // every counted directory and every file on another device than its
+    // directory by device, the mount point of a device is looked up at the
+    // first of its nodes in the final order
+    device_nodes: Vec<(u64, NodeIndex)>,
+
+    // node of every directory counted so far by id, a directory reached
+    // through several paths is counted at the one added first
+    counted_directories: HashMap<NodeId, NodeIndex>,
+
+    // empty nodes of the directories reached again, by node
+    aliases: HashMap<NodeIndex, NodeId>,
+
+    // files linked more than once, which are added once every path to them
+    // is known
+    linked_files: Vec<(NodeIndex, FileInfo)>,
+    sym_links: Vec<(NodeIndex, SymLinkInfo)>,
+    counted_special: Vec<(NodeIndex, SpecialEntry)>,
+    special_files: Vec<(NodeIndex, SpecialFiles)>,
+    excluded: Excluded,
+}
+
+impl TreeBuilder {
+    fn new(root_path: PathBuf, root_size: (u64, Option<u64>), root_details: NodeDetails, record_details: bool) -> TreeBuilder {
+        let mut tree = ScanTree::new(root_path);
+        if record_details {
+            tree.record_details();
+        }
+        let root = tree.root();
+        tree.add_size(root, root_size.0, root_size.1);
+        tree.describe(root, root_details);
+        let mut builder = TreeBuilder {
+            tree,
+            devices: HashMap::new(),
+            device_nodes: vec![(root_details.id.0, root)],
+            counted_directories: HashMap::new(),
+            aliases: HashMap::new(),
+            linked_files: Vec::new(),
+            sym_links: Vec::new(),
+            counted_special: Vec::new(),
+            special_files: Vec::new(),
+            excluded: Excluded::default(),
+        };
+        builder.counted_directories.insert(root_details.id, root);
+        builder.add_usage(root_details.id.0, root_size.0, root_size.1, false);
+        builder
+    }
+
+    // add counted blocks to the usage of a device
+    fn add_usage(&mut self, device: u64, size: u64, apparent_size: Option<u64>, is_file: bool) {
+        let usage = self.devices.entry(device).or_insert_with(|| DeviceUsage {
+            device,
+            mount_point: None,
+            fs_type: None,
+            size: 0,
+            apparent_size: None,
+            files: 0,
+        });
+        usage.size += size;
+        if let Some(apparent_size) = apparent_size {
+            *usage.apparent_size.get_or_insert(0) += apparent_size;
+        }
+        if is_file {
+            usage.files += 1;
+        }
+    }
+
+    // add a file to the tree with the size it is credited with
+    fn add_file(&mut self, directory_index: NodeIndex, file: &FileInfo, size: u64, apparent_size: Option<u64>) -> NodeIndex {
+        let index = self.tree.add_node(directory_index, file_name(&file.node.path), NodeType::File, size, apparent_size);
+        self.tree.describe(index, describe(&file.node));
+        self.add_usage(file.node.id.0, size, apparent_size, true);
+        index
+    }
+
+    // add the entries of a listing below the node of its directory, the
+    // subdirectories by name and then the files in the order they were
+    // listed, `kinds` tells what becomes of every subdirectory, returns the
+    // subdirectories the walker descends into
+    fn add_listing(&mut self, task: &DirectoryTask, directory_result: DirectoryResult, kinds: &[Subdirectory]) -> Vec<DirectoryTask> {
+        let DirectoryResult { directories, files, sym_links, counted_special, special_files, excluded, .. } = directory_result;
+        let directory_index = task.index;
+        let device = task.id().0;
+
+        self.excluded.add(excluded);
+        if special_files.count() > 0 {
+            self.special_files.push((directory_index, special_files));
+        }
+        if !counted_special.is_empty() {
+            let (size, apparent_size) = counted_size(&counted_special);
+            self.tree.add_size(directory_index, size, apparent_size);
+            self.add_usage(device, size, apparent_size, false);
+            self.counted_special.extend(counted_special.into_iter().map(|mut entry| {
+                entry.path = PathBuf::from(file_name(&entry.path));
+                (directory_index, entry)
+            }));
+        }
+        self.sym_links.extend(sym_links.into_iter().map(|mut link| {
+            link.path = PathBuf::from(file_name(&link.path));
+            (directory_index, link)
+        }));
+
+        // a directory this listing descends into is counted even if another
+        // path to it comes first by name, `settle_aliases` moves it there
+        let descended: HashSet<NodeId> = directories
+            .iter()
+            .zip(kinds)
+            .filter(|(_, &kind)| kind == Subdirectory::Descended)
+            .map(|(directory, _)| directory.node.id)
+            .collect();
+        let mut by_name: Vec<usize> = (0..directories.len()).collect();
+        by_name.sort_by(|&a, &b| directories[a].node.path.cmp(&directories[b].node.path));
+        let mut indices: Vec<NodeIndex> = vec![0; directories.len()];
+        for position in by_name {
+            let directory = &directories[position];
+            let name = file_name(&directory.node.path);
+            let id = directory.node.id;
+            let index = match kinds[position] {
+                Subdirectory::Skipped => continue,
+                // aliases stay in the tree as empty directories
+                Subdirectory::Visited if self.counted_directories.contains_key(&id) || descended.contains(&id) => {
+                    let index = self.tree.add_node(directory_index, name, NodeType::Directory, 0, None);
+                    self.aliases.insert(index, id);
+                    index
+                }
+                // a directory first reached while measuring an excluded
+                // entry is counted without its contents
+                Subdirectory::Visited | Subdirectory::Descended => {
+                    let index = self.tree.add_node(directory_index, name, NodeType::Directory, directory.size, directory.apparent_size);
+                    self.counted_directories.insert(id, index);
+                    self.device_nodes.push((id.0, index));
+                    self.add_usage(id.0, directory.size, directory.apparent_size, false);
+                    index
+                }
+            };
+            self.tree.describe(index, describe(&directory.node));
+            indices[position] = index;
+        }
+
+        for mut file in files {
+            if file.links > 1 {
+                file.node.path = PathBuf::from(file_name(&file.node.path));
+                self.linked_files.push((directory_index, file));
+            } else {
+                let index = self.add_file(directory_index, &file, file.size, file.apparent_size);
+                if file.node.id.0 != device {
+                    self.device_nodes.push((file.node.id.0, index));
+                }
+            }
+        }
+
+        directories
+            .iter()
+            .zip(kinds)
+            .zip(indices)
+            .filter(|((_, &kind), _)| kind == Subdirectory::Descended)
+            .map(|((directory, _), index)| task.subdirectory(directory, index))
+            .collect()
+    }
+
+    // move every directory reached through several paths to the first of
+    // them breadth first by name, where a sequential walk counts it, no
+    // matter at which path a walker happened to list it, returns the node
+    // every moved directory was moved to by the node it was listed at
+    fn settle_aliases(&mut self) -> HashMap<NodeIndex, NodeIndex> {
+        let mut moved: HashMap<NodeIndex, NodeIndex> = HashMap::new();
+        if self.aliases.is_empty() {
+            return moved;
+        }
+        let mut counted_ids: HashMap<NodeIndex, NodeId> = self.counted_directories.iter().map(|(&id, &index)| (index, id)).collect();
+        let mut settled: HashSet<NodeId> = HashSet::new();
+        let mut dir_queue: VecDeque<NodeIndex> = VecDeque::from([self.tree.root()]);
+        while let Some(index) = dir_queue.pop_front() {
+            if let Some(&id) = counted_ids.get(&index) {
+                settled.insert(id);
+            } else if let Some(&id) = self.aliases.get(&index) {
+                if settled.insert(id) {
+                    // the directory swaps places with its first alias
+                    let counted = self.counted_directories[&id];
+                    self.tree.move_contents(counted, index);
+                    self.aliases.remove(&index);
+                    self.aliases.insert(counted, id);
+                    counted_ids.remove(&counted);
+                    counted_ids.insert(index, id);
+                    self.counted_directories.insert(id, index);
+                    moved.insert(counted, index);
+                }
+            }
+            let directories = self
+                .tree
+                .children(index)
+                .filter(|(_, node)| node.node_type == NodeType::Directory)
+                .map(|(child, _)| child);
+            dir_queue.extend(directories);
+        }
+        moved
+    }
+}
+
+// build the scan result from the tree of a walk, the tree is put into breadth
+// first order with the subdirectories of every directory sorted by name so
+// the result does not depend on the order in which directories were listed
+fn build(context: ScanContext, mut errors: Vec<ScanError>) -> ScanResult {
+    let hardlink_policy = context.options.hardlink_policy;
+    let mut builder = context.builder.into_inner().unwrap();
+    errors.sort_by(|a, b| a.path.cmp(&b.path));
+
+    // everything kept by the node of a directory follows the directories
+    // moved by `settle_aliases` and then every node to its final place
+    let moved = builder.settle_aliases();
+    let new_index = builder.tree.reorder();
+    let place = |index: NodeIndex| new_index[*moved.get(&index).unwrap_or(&index)];
+    let tree = &builder.tree;
+
+    let mut sym_links: Vec<SymLinkInfo> = std::mem::take(&mut builder.sym_links)
+        .into_iter()
+        .map(|(index, mut link)| {
+            link.path = tree.path(place(index)).join(&link.path);
+            link
+        })
+        .collect();
+    sym_links.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut special_files: Vec<(PathBuf, SpecialFiles)> = std::mem::take(&mut builder.special_files)
+        .into_iter()
+        .map(|(index, special_files)| (tree.path(place(index)), special_files))
+        .collect();
+    special_files.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut counted_special: Vec<(NodeIndex, SpecialEntry)> = std::mem::take(&mut builder.counted_special)
+        .into_iter()
+        .map(|(index, mut entry)| {
+            let index = place(index);
+            entry.path = tree.path(index).join(&entry.path);
+            (index, entry)
+        })
+        .collect();
+    // stable, so the entries of a directory stay in the order they were listed
+    counted_special.sort_by_key(|(index, _)| *index);
+    let mut directory_aliases: Vec<DirectoryAlias> = builder
+        .aliases
+        .iter()
+        .map(|(&index, id)| {
+            let (index, target) = (new_index[index], new_index[builder.counted_directories[id]]);
+            DirectoryAlias {
+                path: tree.path(index),
+                target: tree.path(target),
+                is_loop: tree.is_ancestor(target, index),
+            }
+        })
+        .collect();
+    directory_aliases.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut first_nodes: HashMap<u64, NodeIndex> = HashMap::new();
+    for (device, index) in std::mem::take(&mut builder.device_nodes) {
+        let index = place(index);
+        let first = first_nodes.entry(device).or_insert(index);
+        *first = index.min(*first);
+    }
+
+    // files with several hardlinks inside the scan are credited according to
+    // the hardlink policy once every path to them is known
+    let mut files: Vec<(NodeIndex, FileInfo)> = std::mem::take(&mut builder.linked_files)
+        .into_iter()
+        .map(|(index, mut file)| {
+            let index = place(index);
+            file.node.path = tree.path(index).join(&file.node.path);
+            (index, file)
+        })
+        .collect();
+    files.sort_by(|a, b| a.1.node.path.cmp(&b.1.node.path));
+    let traversal_order = |file: &FileInfo| {
+        let mut order = vec![file.node.position];
+        let mut current = file.node.path.parent().and_then(|directory| tree.find(directory));
+        while let Some(index) = current.filter(|&index| index != tree.root()) {
+            order.push(tree.details(index).map_or(0, |details| details.position));
+            current = tree.get(index).parent;
+        }
+        order.reverse();
+        order
+    };
+    let (credits, hardlinks) = attribute(files.iter().map(|(_, file)| file), hardlink_policy, traversal_order);
+
+    let mut double_count = HashMap::new();
+    for (directory_index, file) in files {
+        let (size, apparent_size) = match credits.get(&file.node.path) {
+            None => (file.size, file.apparent_size),
+            Some(credit) => {
+                if !credit.primary {
+                    double_count.insert(
+                        file.node.path.clone(),
+                        NodeResult {
+                            size: file.size,
+                            apparent_size: file.apparent_size,
+                            node_type: NodeType::File,
+                        },
+                    );
+                    // paths without any share are left out of the tree
+                    if matches!(hardlink_policy, HardlinkPolicy::First | HardlinkPolicy::Shallowest | HardlinkPolicy::Traversal) {
+                        continue;
+                    }
+                }
+                (credit.size, credit.apparent_size)
+            }
+        };
+        let index = builder.add_file(directory_index, &file, size, apparent_size);
+        first_nodes.entry(file.node.id.0).or_insert(index);
+    }
+
+    let TreeBuilder { mut tree, devices, excluded, .. } = builder;
+    let mut devices: Vec<DeviceUsage> = devices
+        .into_values()
+        .map(|mut usage| {
+            let first = first_nodes.get(&usage.device).map(|&index| tree.path(index));
+            if let Some(mount) = first.and_then(|path| mount_of_device(&context.mounts, usage.device, &path)) {
+                usage.mount_point = Some(mount.mount_point.clone());
+                usage.fs_type = Some(mount.fs_type.clone());
+            }
+            usage
+        })
+        .collect();
+    devices.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.device.cmp(&b.device)));
+
+    tree.compute_totals();
+    tree.sort_children();
+    ScanResult {
+        tree,
+        double_count,
+        hardlinks,
+        sym_links,
+        skipped_mounts: Vec::new(),
+        directory_aliases,
+        devices,
+        excluded,
+        special_files,
+        counted_special,
+        errors,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeSet;
+
+    use crate::test_support::{directory_totals, sample, wide, Fixture};
+    use crate::types::FollowSymlinks;
+    use crate::walk::SequentialWalker;
+    use crate::walk_async::ThreadedWalker;
+
+    fn double_counted(scan: &ScanResult) -> BTreeSet<PathBuf> {
+        scan.double_count.keys().cloned().collect()
+    }
+
+    #[test]
+    fn test_walkers_agree() {
+        let fixture = sample();
+        let options = ScanOptions::default();
+        let expected = scan(&SequentialWalker, fixture.root.clone(), &options).unwrap();
+        for threads in [1, 2, 4, 8] {
+            let actual = scan(&ThreadedWalker { threads }, fixture.root.clone(), &options).unwrap();
+            assert_eq!(actual.tree.to_map(), expected.tree.to_map(), "{} threads", threads);
+            assert_eq!(double_counted(&actual), double_counted(&expected), "{} threads", threads);
+        }
+    }
+
+    #[test]
+    fn test_walkers_list_every_directory_once() {
+        let fixture = wide();
+        let options = ScanOptions::default();
+        let listed = |walker: &dyn Walker| {
+            let context = ScanContext::new(fixture.root.clone(), &options).unwrap();
+            walker.walk(&context).unwrap();
+            let tree = context.builder.into_inner().unwrap().tree;
+            let mut paths: Vec<PathBuf> = tree
+                .iter()
+                .filter(|(_, node)| node.node_type == NodeType::Directory)
+                .map(|(index, _)| tree.path(index))
+                .collect();
+            paths.sort();
+            paths
+        };
+        let expected = listed(&SequentialWalker);
+        assert_eq!(expected.len(), 1 + 8 + 64 * 3);
+        for threads in [1, 2, 4, 8] {
+            // sorted but not deduplicated, so a listing lost or added twice shows
+            let actual = listed(&ThreadedWalker { threads });
+            assert_eq!(actual, expected, "{} threads", threads);
+        }
+    }
+
+    #[test]
+    fn test_listings_are_added_as_listed() {
+        // nothing of a listing outlives it but its nodes, the files linked
+        // more than once are kept by their names until they are credited
+        let fixture = sample();
+        let options = ScanOptions { hardlink_policy: HardlinkPolicy::Everywhere, ..ScanOptions::default() };
+        let nodes = scan(&SequentialWalker, fixture.root.clone(), &options).unwrap().tree.len();
+        for threads in [1, 4] {
+            let context = ScanContext::new(fixture.root.clone(), &options).unwrap();
+            ThreadedWalker { threads }.walk(&context).unwrap();
+            let builder = context.builder.into_inner().unwrap();
+            assert_eq!(builder.tree.len() + builder.linked_files.len(), nodes);
+            let names: BTreeSet<&Path> = builder.linked_files.iter().map(|(_, file)| file.node.path.as_path()).collect();
+            let expected = ["two", "two-link", "five", "five-link"].map(Path::new);
+            assert_eq!(names, BTreeSet::from(expected));
+        }
+    }
+
+    #[test]
+    fn test_scans_are_repeatable() {
+        let fixture = sample();
+        let options = ScanOptions::default();
+        let first = scan(&ThreadedWalker { threads: 4 }, fixture.root.clone(), &options).unwrap();
+        for _ in 0..5 {
+            let again = scan(&ThreadedWalker { threads: 4 }, fixture.root.clone(), &options).unwrap();
+            assert_eq!(again.tree.to_map(), first.tree.to_map());
+            assert_eq!(double_counted(&again), double_counted(&first));
+        }
+    }
+
+    #[test]
+    fn test_totals_stop_at_root() {
+        let fixture = sample();
+        let options = ScanOptions::default();
+        for scan in [
+            scan(&SequentialWalker, fixture.root.clone(), &options).unwrap(),
+            scan(&ThreadedWalker { threads: 3 }, fixture.root.clone(), &options).unwrap(),
+        ] {
+            assert!(scan.tree.to_map().keys().all(|path| path.starts_with(&fixture.root)));
+
+            // every inode is counted exactly once, the exclusive size of a
+            // directory holds its own blocks and those of its files
+            let root_size = scan.tree.get(scan.tree.root()).size;
+            let exclusive_sizes: u64 = scan
+                .tree
+                .iter()
+                .filter(|(_, node)| node.node_type == NodeType::Directory)
+                .map(|(_, node)| node.exclusive_size)
+                .sum();
+            assert_eq!(root_size, exclusive_sizes);
+            let directory = std::fs::symlink_metadata(fixture.path("a")).unwrap();
+            let a = scan.tree.find(&fixture.path("a")).unwrap();
+            assert!(scan.tree.get(a).exclusive_size >= crate::file_size::file_size(&directory));
+            assert_eq!(scan.double_count.len(), 2);
+
+            // the fixture lives on a single device
+            assert_eq!(scan.devices.len(), 1);
+            assert_eq!(scan.devices[0].size, root_size);
+            assert_eq!(scan.devices[0].files, 6);
+        }
+    }
+
+    #[test]
+    fn test_error_policies() {
+        let fixture = Fixture::new("errors");
+        fixture.file("readable/file", 10);
+        let reachable = fixture.too_deep("deep");
+        let policy = |error_policy: ErrorPolicy| ScanOptions { error_policy, ..ScanOptions::default() };
+
+        for threads in [1, 4] {
+            let continued = scan(&ThreadedWalker { threads }, fixture.root.clone(), &policy(ErrorPolicy::Continue)).unwrap();
+            assert_eq!(continued.errors.len(), 1);
+            let error = &continued.errors[0];
+            assert!(error.path.starts_with(&reachable));
+            assert_eq!(error.operation, ScanOperation::Metadata);
+            assert!(continued.tree.find(&fixture.path("readable/file")).is_some());
+            assert!(continued.tree.find(&reachable).is_some());
+
+            let error = scan(&ThreadedWalker { threads }, fixture.root.clone(), &policy(ErrorPolicy::FailFast)).unwrap_err();
+            assert_eq!(error.operation, ScanOperation::Metadata);
+        }
+        assert!(scan(&SequentialWalker, fixture.root.clone(), &policy(ErrorPolicy::FailFast)).is_err());
+    }
+
+    // lists breadth first like `SequentialWalker` but every directory's
+    // subdirectories in reverse order of their paths
+    struct ReverseWalker;
+
+    impl Walker for ReverseWalker {
+        fn walk(&self, context: &ScanContext) -> Result<Vec<ScanError>, ScanError> {
+            let mut dir_queue: VecDeque<DirectoryTask> = VecDeque::from([context.root_task()]);
+            while let Some(task) = dir_queue.pop_front() {
+                let mut directory_result = crate::process_directory::process_directory(&task.path, context)?;
+                directory_result.directories.sort_by(|a, b| b.node.path.cmp(&a.node.path));
+                dir_queue.extend(context.listed(task, directory_result));
+            }
+            Ok(Vec::new())
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_moved_directories() {
+        let outside = Fixture::new("moved-target");
+        outside.file("data/blob", 20000);
+        let fixture = Fixture::new("moved");
+        fixture.file("p/file", 10).file("q/file", 10).symlink(outside.path("data"), "p/link").symlink(outside.path("data"), "q/link");
+
+        // the walker lists the external directory below `q`, the tree holds
+        // it below `p` like after a sequential walk
+        let options = ScanOptions { follow_symlinks: FollowSymlinks::Always, ..ScanOptions::default() };
+        let moved = scan(&ReverseWalker, fixture.root.clone(), &options).unwrap();
+        let expected = scan(&SequentialWalker, fixture.root.clone(), &options).unwrap();
+        assert_eq!(directory_totals(&moved.tree), directory_totals(&expected.tree));
+        assert!(moved.tree.find(&fixture.path("p/link/blob")).is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_directory_aliases() {
+        // two links to the same directory outside of the root and a chain of
+        // links out there that leads back into it
+        let outside = Fixture::new("aliased");
+        outside
+            .file("a/file", 3000)
+            .hard_link("a/file", "a/file-link")
+            .dir("b")
+            .symlink(outside.path("b"), "a/to-b")
+            .symlink(outside.path("a"), "b/to-a");
+        let fixture = Fixture::new("aliases");
+        fixture.symlink(outside.path("a"), "x").symlink(outside.path("a"), "y");
+
+        let options = ScanOptions { follow_symlinks: FollowSymlinks::Always, ..ScanOptions::default() };
+        let expected = scan(&SequentialWalker, fixture.root.clone(), &options).unwrap();
+        let aliases: Vec<(PathBuf, PathBuf, bool)> = expected
+            .directory_aliases
+            .iter()
+            .map(|alias| (alias.path.clone(), alias.target.clone(), alias.is_loop))
+            .collect();
+        assert_eq!(aliases, vec![
+            (fixture.path("x/to-b/to-a"), fixture.path("x"), true),
+            (fixture.path("y"), fixture.path("x"), false),
+        ]);
+        assert_eq!(expected.hardlinks[0].paths, vec![fixture.path("x/file"), fixture.path("x/file-link")]);
+
+        // whichever path a thread lists the directory at, everything is
+        // reported below the first one
+        for threads in [1, 4] {
+            for _ in 0..5 {
+                let actual = scan(&ThreadedWalker { threads }, fixture.root.clone(), &options).unwrap();
+                assert_eq!(actual.tree.to_map(), expected.tree.to_map());
+                assert_eq!(actual.hardlinks[0].paths, expected.hardlinks[0].paths);
+                assert_eq!(double_counted(&actual), double_counted(&expected));
+                assert_eq!(actual.directory_aliases.len(), 2);
+                assert_eq!(actual.devices[0].mount_point, expected.devices[0].mount_point);
+            }
+        }
+    }
+}